@@ -0,0 +1,573 @@
+//! Semantic analysis passes that run on the AST.
+
+use crate::{
+    codegen::fold_constant,
+    error::{CompileError, SemanticWarning},
+    parse::ast::{
+        walk_expr, walk_item, Expr, ExprKind, Identifier, Item, ItemKind, LetVar, Visitor,
+    },
+    source::FileId,
+    span::{Locatable, Span},
+};
+use lasso::{Spur, ThreadedRodeo};
+use smol_str::SmolStr;
+use std::collections::{HashMap, HashSet};
+
+/// Finds all function parameters and `var` bindings that are never used.
+pub fn unused_variables(
+    rodeo: &ThreadedRodeo,
+    file: FileId,
+    items: &[Item],
+) -> Vec<Locatable<SemanticWarning>> {
+    let mut visitor = UnusedVariables {
+        rodeo,
+        file,
+        scope: Vec::new(),
+        warnings: Vec::new(),
+    };
+    for item in items {
+        visitor.visit_item(item);
+    }
+    visitor.warnings
+}
+
+/// A variable that is currently in scope.
+struct Binding {
+    spur: Spur,
+    span: Span,
+    used: bool,
+    /// Loop variables are bindings too, but they are never reported.
+    report: bool,
+}
+
+struct UnusedVariables<'a> {
+    rodeo: &'a ThreadedRodeo,
+    file: FileId,
+    /// All bindings that are in scope. Inner bindings come last,
+    /// so they shadow outer bindings with the same name.
+    scope: Vec<Binding>,
+    warnings: Vec<Locatable<SemanticWarning>>,
+}
+
+impl UnusedVariables<'_> {
+    fn bind(&mut self, name: &Identifier, report: bool) {
+        self.scope.push(Binding {
+            spur: name.spur,
+            span: name.span,
+            used: false,
+            report,
+        });
+    }
+
+    /// Removes the last `count` bindings from the scope and reports
+    /// the ones that were never used.
+    fn unbind(&mut self, count: usize) {
+        let start = self.scope.len() - count;
+        for binding in self.scope.drain(start..) {
+            if binding.report && !binding.used {
+                let name = self.rodeo.resolve(&binding.spur).into();
+                let warning = SemanticWarning::UnusedVariable { name };
+                self.warnings.push(binding.span.locate(self.file, warning));
+            }
+        }
+    }
+}
+
+impl Visitor for UnusedVariables<'_> {
+    fn visit_item(&mut self, item: &Item) {
+        match &item.kind {
+            ItemKind::Function { args, body, .. } | ItemKind::Operator { args, body, .. } => {
+                for arg in args {
+                    self.bind(arg, true);
+                }
+                self.visit_expr(body);
+                self.unbind(args.len());
+            }
+            ItemKind::Global { .. } => walk_item(self, item),
+            ItemKind::Extern { .. } | ItemKind::Import { .. } => {}
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Var(name) => {
+                if let Some(binding) = self.scope.iter_mut().rev().find(|b| b.spur == name.spur) {
+                    binding.used = true;
+                }
+            }
+            ExprKind::For {
+                var,
+                start,
+                end,
+                step,
+                body,
+            } => {
+                self.visit_expr(start);
+                self.bind(var, false);
+                self.visit_expr(end);
+                if let Some(step) = step {
+                    self.visit_expr(step);
+                }
+                self.visit_expr(body);
+                self.unbind(1);
+            }
+            ExprKind::Let { vars, body } => {
+                // Every initializer can see the variables that were declared before it.
+                for LetVar { name, val } in vars {
+                    if let Some(val) = val {
+                        self.visit_expr(val);
+                    }
+                    self.bind(name, true);
+                }
+                self.visit_expr(body);
+                self.unbind(vars.len());
+            }
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+/// Finds `for` loops with constant bounds that run only once or never terminate.
+///
+/// Only end conditions that compare the loop variable with a constant using
+/// `<`, `<=`, `>` or `>=` are checked.
+pub fn loop_bounds(file: FileId, items: &[Item]) -> Vec<Locatable<SemanticWarning>> {
+    let mut visitor = LoopBounds {
+        file,
+        warnings: Vec::new(),
+    };
+    for item in items {
+        visitor.visit_item(item);
+    }
+    visitor.warnings
+}
+
+struct LoopBounds {
+    file: FileId,
+    warnings: Vec<Locatable<SemanticWarning>>,
+}
+
+impl Visitor for LoopBounds {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let ExprKind::For {
+            var,
+            start,
+            end,
+            step,
+            ..
+        } = &expr.kind
+        {
+            if let Some(warning) = check_loop_bounds(var, start, end, step.as_deref()) {
+                self.warnings.push(end.span.locate(self.file, warning));
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+fn check_loop_bounds(
+    var: &Identifier,
+    start: &Expr,
+    end: &Expr,
+    step: Option<&Expr>,
+) -> Option<SemanticWarning> {
+    let (op, bound) = match &end.kind {
+        ExprKind::Binary {
+            left, op, right, ..
+        } => match &left.kind {
+            ExprKind::Var(name) if name.spur == var.spur => (op, fold_constant(right)?),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let start = fold_constant(start)?.into_inner();
+    let step = match step {
+        Some(step) => fold_constant(step)?.into_inner(),
+        None => 1.0,
+    };
+
+    let bound = bound.into_inner();
+    let (holds, away) = match op.as_str() {
+        "<" => (start < bound, step <= 0.0),
+        "<=" => (start <= bound, step <= 0.0),
+        ">" => (start > bound, step >= 0.0),
+        ">=" => (start >= bound, step >= 0.0),
+        _ => return None,
+    };
+
+    if !holds {
+        Some(SemanticWarning::LoopRunsOnce)
+    } else if away || bound.is_infinite() {
+        Some(SemanticWarning::InfiniteLoop)
+    } else {
+        None
+    }
+}
+
+/// Finds all unknown variables, unknown functions and calls with the wrong
+/// number of arguments, before any code is generated.
+///
+/// Unlike codegen, this doesn't stop at the first error.
+pub fn resolve_names(
+    rodeo: &ThreadedRodeo,
+    file: FileId,
+    items: &[Item],
+) -> Vec<Locatable<CompileError>> {
+    resolve_imported_names(rodeo, file, items, &[])
+}
+
+/// Like `resolve_names`, but the definitions of `imported` are also visible.
+///
+/// Only the names used inside `items` are resolved.
+pub fn resolve_imported_names(
+    rodeo: &ThreadedRodeo,
+    file: FileId,
+    items: &[Item],
+    imported: &[Item],
+) -> Vec<Locatable<CompileError>> {
+    let functions = imported
+        .iter()
+        .chain(items)
+        .filter_map(|item| match &item.kind {
+            ItemKind::Function { name, args, .. } => Some((name.spur, (args.len(), false))),
+            ItemKind::Extern {
+                name,
+                args,
+                variadic,
+                ..
+            } => Some((name.spur, (args.len(), *variadic))),
+            ItemKind::Operator { .. } | ItemKind::Global { .. } | ItemKind::Import { .. } => None,
+        })
+        .collect();
+    let globals = imported
+        .iter()
+        .chain(items)
+        .filter_map(|item| match &item.kind {
+            ItemKind::Global { name, .. } => Some(name.spur),
+            _ => None,
+        })
+        .collect();
+
+    let mut visitor = NameResolver {
+        rodeo,
+        file,
+        functions,
+        globals,
+        scope: Vec::new(),
+        errors: Vec::new(),
+    };
+    for item in items {
+        visitor.visit_item(item);
+    }
+    visitor.errors
+}
+
+struct NameResolver<'a> {
+    rodeo: &'a ThreadedRodeo,
+    file: FileId,
+    /// The number of arguments of every function, and if it's variadic.
+    functions: HashMap<Spur, (usize, bool)>,
+    /// All global variables, which are visible in every item.
+    globals: HashSet<Spur>,
+    /// All variables that are currently in scope.
+    scope: Vec<Spur>,
+    errors: Vec<Locatable<CompileError>>,
+}
+
+impl NameResolver<'_> {
+    /// Finds the closest name among the given names to suggest it instead of `name`.
+    fn suggest<'s>(&self, name: Spur, names: impl Iterator<Item = &'s Spur>) -> Option<SmolStr> {
+        let names = names.map(|spur| self.rodeo.resolve(spur));
+        closest_name(self.rodeo.resolve(&name), names).map(Into::into)
+    }
+}
+
+impl Visitor for NameResolver<'_> {
+    fn visit_item(&mut self, item: &Item) {
+        match &item.kind {
+            ItemKind::Function { args, body, .. } | ItemKind::Operator { args, body, .. } => {
+                self.scope.extend(args.iter().map(|arg| arg.spur));
+                self.visit_expr(body);
+                self.scope.clear();
+            }
+            ItemKind::Global { .. } => walk_item(self, item),
+            ItemKind::Extern { .. } | ItemKind::Import { .. } => {}
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Var(name) => {
+                if !self.scope.contains(&name.spur) && !self.globals.contains(&name.spur) {
+                    let suggestion =
+                        self.suggest(name.spur, self.scope.iter().chain(self.globals.iter()));
+                    let err = CompileError::UnknownVariable { suggestion };
+                    self.errors.push(expr.span.locate(self.file, err));
+                }
+            }
+            ExprKind::Call { callee, args } => {
+                match self.functions.get(&callee.spur) {
+                    None => {
+                        let suggestion = self.suggest(callee.spur, self.functions.keys());
+                        let err = CompileError::UnknownFunction { suggestion };
+                        self.errors.push(expr.span.locate(self.file, err));
+                    }
+                    Some(&(expected, variadic))
+                        if args.len() < expected || (!variadic && args.len() > expected) =>
+                    {
+                        let err = CompileError::InvalidArguments {
+                            expected,
+                            found: args.len(),
+                        };
+                        self.errors.push(expr.span.locate(self.file, err));
+                    }
+                    Some(_) => {}
+                }
+                walk_expr(self, expr);
+            }
+            ExprKind::For {
+                var,
+                start,
+                end,
+                step,
+                body,
+            } => {
+                self.visit_expr(start);
+                self.scope.push(var.spur);
+                self.visit_expr(end);
+                if let Some(step) = step {
+                    self.visit_expr(step);
+                }
+                self.visit_expr(body);
+                self.scope.pop();
+            }
+            ExprKind::Let { vars, body } => {
+                let len = self.scope.len();
+                for LetVar { name, val } in vars {
+                    if let Some(val) = val {
+                        self.visit_expr(val);
+                    }
+                    self.scope.push(name.spur);
+                }
+                self.visit_expr(body);
+                self.scope.truncate(len);
+            }
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+/// Finds the name that is most similar to `name`, so it can be suggested
+/// if `name` is not defined.
+///
+/// Only names that differ in at most a third of the characters of `name` are similar.
+pub fn closest_name<'a>(name: &str, names: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = name.chars().count() / 3;
+    names
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (levenshtein(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        // Ties are broken by the name, so the result doesn't depend on the order of `names`.
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Computes the Levenshtein distance between two strings, counted in characters.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + if a == *b { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::Parser;
+    use std::sync::Arc;
+
+    fn analyze(code: &str) -> Vec<Locatable<SemanticWarning>> {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let items = Parser::new(Arc::clone(&rodeo), code, FileId::default())
+            .parse()
+            .unwrap();
+        unused_variables(&rodeo, FileId::default(), &items)
+    }
+
+    #[test]
+    fn unused_parameter() {
+        let warnings = analyze("def f(x y) x;");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].span(), Span::new(8, 9));
+        assert_eq!(
+            warnings[0].data(),
+            &SemanticWarning::UnusedVariable { name: "y".into() }
+        );
+    }
+
+    #[test]
+    fn shadowed_parameter() {
+        let warnings = analyze("def f(x) var x = 1 in x;");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].span(), Span::new(6, 7));
+    }
+
+    fn loop_warnings(code: &str) -> Vec<Locatable<SemanticWarning>> {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let items = Parser::new(rodeo, code, FileId::default()).parse().unwrap();
+        loop_bounds(FileId::default(), &items)
+    }
+
+    #[test]
+    fn loop_runs_once() {
+        let warnings = loop_warnings("def f(x) for i = 10, i < 0 in x;");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].data(), &SemanticWarning::LoopRunsOnce);
+        assert_eq!(warnings[0].span(), Span::new(21, 26));
+
+        let warnings = loop_warnings("def f(x) for i = 0, i >= 1, -1 in x;");
+        assert_eq!(warnings[0].data(), &SemanticWarning::LoopRunsOnce);
+    }
+
+    #[test]
+    fn infinite_loop() {
+        // The product of two huge literals is infinite.
+        let huge = format!("1{}", "0".repeat(200));
+        let infinite_bound = format!("def f(x) for i = 0, i < {} * {} in x;", huge, huge);
+        for code in &[
+            "def f(x) for i = 0, i < 10, -1 in x;",
+            "def f(x) for i = 0, i > -10 in x;",
+            "def f(x) for i = 0, i < 10 in for j = 0, j > -1 in x;",
+            infinite_bound.as_str(),
+        ] {
+            let warnings = loop_warnings(code);
+            assert_eq!(warnings.len(), 1, "{}", code);
+            assert_eq!(warnings[0].data(), &SemanticWarning::InfiniteLoop);
+        }
+    }
+
+    #[test]
+    fn valid_loops() {
+        assert!(loop_warnings("def f(x) for i = 0, i < 10 in x;").is_empty());
+        assert!(loop_warnings("def f(x) for i = 10, i >= 0, -2 in x;").is_empty());
+        // Bounds that aren't constant are not checked.
+        assert!(loop_warnings("def f(x) for i = 0, i < x, -1 in x;").is_empty());
+        assert!(loop_warnings("def f(x) for i = 0, x < 10 in x;").is_empty());
+    }
+
+    #[test]
+    fn used_variables() {
+        let warnings = analyze("def f(x) var y = x in for i = 0, i < y in y;");
+        assert!(warnings.is_empty());
+    }
+
+    fn resolve(code: &str) -> Vec<Locatable<CompileError>> {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let items = Parser::new(Arc::clone(&rodeo), code, FileId::default())
+            .parse()
+            .unwrap();
+        resolve_names(&rodeo, FileId::default(), &items)
+    }
+
+    #[test]
+    fn unknown_variables() {
+        let errors = resolve("def f() x + y;");
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].data(),
+            &CompileError::UnknownVariable { suggestion: None }
+        );
+        assert_eq!(errors[0].span(), Span::new(8, 9));
+        assert_eq!(
+            errors[1].data(),
+            &CompileError::UnknownVariable { suggestion: None }
+        );
+        assert_eq!(errors[1].span(), Span::new(12, 13));
+    }
+
+    #[test]
+    fn unknown_functions_and_arity() {
+        let errors = resolve("def f(x) x; def g(a) f(a, a) + h(a);");
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].data(),
+            &CompileError::InvalidArguments {
+                expected: 1,
+                found: 2
+            }
+        );
+        assert_eq!(
+            errors[1].data(),
+            &CompileError::UnknownFunction { suggestion: None }
+        );
+    }
+
+    #[test]
+    fn variadic_arity() {
+        let errors =
+            resolve("extern printd(x ...); def f() printd(1) + printd(1, 2, 3) + printd();");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].data(),
+            &CompileError::InvalidArguments {
+                expected: 1,
+                found: 0
+            }
+        );
+    }
+
+    #[test]
+    fn suggest_similar_names() {
+        let errors = resolve("def foo(count) fooo(cont); var total; def bar() totl;");
+        assert_eq!(errors.len(), 3);
+        assert_eq!(
+            errors[0].data(),
+            &CompileError::UnknownFunction {
+                suggestion: Some("foo".into())
+            }
+        );
+        assert_eq!(
+            errors[1].data(),
+            &CompileError::UnknownVariable {
+                suggestion: Some("count".into())
+            }
+        );
+        assert_eq!(
+            errors[2].data(),
+            &CompileError::UnknownVariable {
+                suggestion: Some("total".into())
+            }
+        );
+    }
+
+    #[test]
+    fn closest_names() {
+        assert_eq!(closest_name("fooo", vec!["bar", "foo"]), Some("foo"));
+        assert_eq!(closest_name("fooo", vec!["fob", "baz"]), None);
+        assert_eq!(closest_name("x", vec!["y"]), None);
+        assert_eq!(closest_name("abcd", vec!["abc", "abce"]), Some("abc"));
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("äb", "ab"), 1);
+    }
+
+    #[test]
+    fn resolve_globals() {
+        let errors = resolve("def f() g + 1; var g = 2;");
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn resolved_names() {
+        let errors = resolve("extern sin(x); def f(x) var y = x in for i = 0, i < y in sin(i);");
+        assert!(errors.is_empty());
+    }
+}
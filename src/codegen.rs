@@ -5,23 +5,232 @@
 //! [`inkwell`]: https://github.com/TheDan64/inkwell
 
 use crate::{
-    error::{CompileError, CompileResult},
-    parse::ast::{Expr, ExprKind, Identifier, Item, ItemKind, LetVar},
+    analysis::closest_name,
+    error::{CompileError, CompileResult, RunError},
+    parse::{
+        ast::{Expr, ExprKind, Identifier, Item, ItemKind, LetVar, ReturnType},
+        FrontendDatabase,
+    },
     source::FileId,
     span::Span,
+    Diagnostic,
 };
 use inkwell::{
     builder::Builder,
     context::Context,
-    module::Module,
+    module::{Linkage, Module},
     passes::PassManager,
     types::BasicTypeEnum,
-    values::{BasicValue, FloatValue, FunctionValue, PointerValue},
+    values::{BasicValue, FloatValue, FunctionValue, IntValue, PointerValue},
     FloatPredicate, OptimizationLevel,
 };
 use lasso::{Spur, ThreadedRodeo};
+use ordered_float::NotNan;
 use smol_str::SmolStr;
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, fmt, sync::Arc};
+
+/// Converts a numeric optimization level (`0` to `3`) into an `OptimizationLevel`.
+pub fn parse_opt_level(level: &str) -> Result<OptimizationLevel, String> {
+    match level.trim() {
+        "0" => Ok(OptimizationLevel::None),
+        "1" => Ok(OptimizationLevel::Less),
+        "2" => Ok(OptimizationLevel::Default),
+        "3" => Ok(OptimizationLevel::Aggressive),
+        level => Err(format!(
+            "invalid optimization level '{}', expected 0, 1, 2 or 3",
+            level
+        )),
+    }
+}
+
+/// Creates a function pass manager for the given module.
+///
+/// If the optimization level is not `None`, the standard optimization
+/// passes will be added to the pass manager.
+pub fn create_pass_manager<'ctx>(
+    module: &Module<'ctx>,
+    level: OptimizationLevel,
+) -> PassManager<FunctionValue<'ctx>> {
+    let fpm = PassManager::create(module);
+    if level != OptimizationLevel::None {
+        fpm.add_promote_memory_to_register_pass();
+        fpm.add_instruction_combining_pass();
+        fpm.add_reassociate_pass();
+        fpm.add_gvn_pass();
+        fpm.add_cfg_simplification_pass();
+    }
+    fpm.initialize();
+    fpm
+}
+
+#[salsa::query_group(CodegenDatabaseStorage)]
+pub trait CodegenDatabase: FrontendDatabase {
+    /// Compiles the given file without optimizations and returns the IR of its module.
+    ///
+    /// The IR is cached as text, because LLVM values can't be stored in the database.
+    /// Imported files are not compiled into the module.
+    fn compile_ir(&self, file: FileId) -> Result<Arc<String>, Diagnostic>;
+}
+
+fn compile_ir(db: &dyn CodegenDatabase, file: FileId) -> Result<Arc<String>, Diagnostic> {
+    let items = db.parse(file).map_err(Into::<Diagnostic>::into)?;
+
+    let ctx = Context::create();
+    let builder = ctx.create_builder();
+    let module = ctx.create_module(&db.name(file));
+    let fpm = create_pass_manager(&module, OptimizationLevel::None);
+
+    let mut compiler = Compiler::new(file, &ctx, &builder, &fpm, &module, db.rodeo());
+    let ir = compiler
+        .compile_to_ir_string(&items)
+        .map_err(Into::<Diagnostic>::into)?;
+    Ok(Arc::new(ir))
+}
+
+/// The calling convention of C functions.
+const C_CALL_CONV: u32 = 0;
+
+/// The LLVM version that inkwell is built against.
+///
+/// This has to match the inkwell branch in `Cargo.toml`.
+const LLVM_VERSION: &str = "10.0";
+
+mod libm {
+    extern "C" {
+        pub fn sin(x: f64) -> f64;
+        pub fn cos(x: f64) -> f64;
+    }
+}
+
+/// Returns the names and addresses of the runtime functions that are
+/// mapped into the JIT, so they can be called from Kaleidoscope code.
+fn builtins() -> [(&'static str, usize); 4] {
+    [
+        ("putchard", crate::putchard as usize),
+        ("printd", crate::printd as usize),
+        ("sin", libm::sin as usize),
+        ("cos", libm::cos as usize),
+    ]
+}
+
+/// Returns the predicate for the given builtin comparison operator.
+fn comparison_predicate(op: &str) -> Option<FloatPredicate> {
+    match op {
+        "<" => Some(FloatPredicate::ULT),
+        ">" => Some(FloatPredicate::UGT),
+        "<=" => Some(FloatPredicate::ULE),
+        ">=" => Some(FloatPredicate::UGE),
+        "==" => Some(FloatPredicate::UEQ),
+        "!=" => Some(FloatPredicate::UNE),
+        _ => None,
+    }
+}
+
+/// Evaluates the expression if it only consists of number literals
+/// and builtin operators.
+///
+/// User defined operators are never folded.
+/// Returns `None` if the expression is not constant, if it would evaluate to `NaN`,
+/// or if it divides by zero.
+pub(crate) fn fold_constant(expr: &Expr) -> Option<NotNan<f64>> {
+    let (left, op, right) = match &expr.kind {
+        ExprKind::Number(x) => return Some(*x),
+        ExprKind::Unary { op: '!', val } => {
+            let val = fold_constant(val)?.into_inner();
+            return NotNan::new(if val == 0.0 { 1.0 } else { 0.0 }).ok();
+        }
+        ExprKind::Binary {
+            left, op, right, ..
+        } => (left, op, right),
+        _ => return None,
+    };
+
+    let lhs = fold_constant(left)?.into_inner();
+    let rhs = fold_constant(right)?.into_inner();
+    let bool_value = |b: bool| if b { 1.0 } else { 0.0 };
+    let value = match op.as_str() {
+        "+" => lhs + rhs,
+        "-" => lhs - rhs,
+        "*" => lhs * rhs,
+        "/" if rhs == 0.0 => return None,
+        "/" => lhs / rhs,
+        "<" => bool_value(lhs < rhs),
+        ">" => bool_value(lhs > rhs),
+        "<=" => bool_value(lhs <= rhs),
+        ">=" => bool_value(lhs >= rhs),
+        "==" => bool_value(lhs == rhs),
+        "!=" => bool_value(lhs != rhs),
+        "&&" => bool_value(lhs != 0.0 && rhs != 0.0),
+        "||" => bool_value(lhs != 0.0 || rhs != 0.0),
+        _ => return None,
+    };
+    NotNan::new(value).ok()
+}
+
+/// Replaces every constant subexpression of `expr` by a single number.
+///
+/// The tree is walked only once, because every node is folded
+/// after its children, so `fold_constant` never has to recurse.
+fn fold_constants(expr: &mut Expr) {
+    match &mut expr.kind {
+        ExprKind::Number(_) | ExprKind::Var(_) => return,
+        ExprKind::Unary { val, .. } => fold_constants(val),
+        ExprKind::Binary { left, right, .. } => {
+            fold_constants(left);
+            fold_constants(right);
+        }
+        ExprKind::Call { args, .. } | ExprKind::Block { exprs: args } => {
+            for arg in args {
+                fold_constants(arg);
+            }
+        }
+        ExprKind::If { cond, then, else_ } => {
+            fold_constants(cond);
+            fold_constants(then);
+            if let Some(else_) = else_ {
+                fold_constants(else_);
+            }
+        }
+        ExprKind::For {
+            start,
+            end,
+            step,
+            body,
+            ..
+        } => {
+            fold_constants(start);
+            fold_constants(end);
+            if let Some(step) = step {
+                fold_constants(step);
+            }
+            fold_constants(body);
+        }
+        ExprKind::While { cond, body } => {
+            fold_constants(cond);
+            fold_constants(body);
+        }
+        ExprKind::Let { vars, body } => {
+            for var in vars {
+                if let Some(val) = &mut var.val {
+                    fold_constants(val);
+                }
+            }
+            fold_constants(body);
+        }
+    }
+
+    let is_number = |expr: &Expr| matches!(expr.kind, ExprKind::Number(_));
+    let constant = match &expr.kind {
+        ExprKind::Unary { val, .. } => is_number(val),
+        ExprKind::Binary { left, right, .. } => is_number(left) && is_number(right),
+        _ => false,
+    };
+    if constant {
+        if let Some(value) = fold_constant(expr) {
+            expr.kind = ExprKind::Number(value);
+        }
+    }
+}
 
 /// The LLVM compiler.
 pub struct Compiler<'r, 'ctx> {
@@ -31,6 +240,10 @@ pub struct Compiler<'r, 'ctx> {
     fpm: &'r PassManager<FunctionValue<'ctx>>,
 
     vars: HashMap<Spur, PointerValue<'ctx>>,
+    /// All global variables of the module.
+    globals: HashMap<Spur, PointerValue<'ctx>>,
+    /// All functions that were declared or defined in the module.
+    functions: HashMap<Spur, FunctionValue<'ctx>>,
     rodeo: Arc<ThreadedRodeo>,
     file: FileId,
 }
@@ -50,37 +263,72 @@ impl<'r, 'ctx> Compiler<'r, 'ctx> {
             module,
             fpm,
             vars: HashMap::new(),
+            globals: HashMap::new(),
+            functions: HashMap::new(),
             rodeo,
             file,
         }
     }
 
+    /// Returns the version of LLVM that is used for codegen.
+    ///
+    /// Linker errors are usually caused by a different LLVM version being installed.
+    pub fn llvm_version() -> &'static str {
+        LLVM_VERSION
+    }
+
     /// Tries to find a `main` function, runs it and returns the result.
-    pub fn run_main(&self) -> Option<f64> {
+    pub fn run_main(&self, level: OptimizationLevel) -> Result<f64, RunError> {
         let jit = self
             .module
-            .create_jit_execution_engine(OptimizationLevel::None)
-            .unwrap();
+            .create_jit_execution_engine(level)
+            .map_err(|err| RunError::Jit(err.to_string()))?;
+        for (name, address) in builtins().iter() {
+            if let Some(fun) = self.module.get_function(name) {
+                jit.add_global_mapping(&fun, *address);
+            }
+        }
 
-        let fun = unsafe { jit.get_function::<unsafe extern "C" fn() -> f64>("main") }.ok()?;
-        Some(unsafe { fun.call() })
+        let fun = unsafe { jit.get_function::<unsafe extern "C" fn() -> f64>("main") }
+            .map_err(|_| RunError::NoMain)?;
+        Ok(unsafe { fun.call() })
+    }
+
+    /// Looks up a local variable, or a global if there's no local with the name.
+    #[inline]
+    fn get_variable(&self, name: Spur) -> Option<PointerValue<'ctx>> {
+        self.vars
+            .get(&name)
+            .or_else(|| self.globals.get(&name))
+            .copied()
+    }
+
+    /// Finds the name of the variable in scope that is closest to `name`,
+    /// to suggest it if `name` is unknown.
+    fn suggest_variable(&self, name: Spur) -> Option<SmolStr> {
+        let names = self
+            .vars
+            .keys()
+            .chain(self.globals.keys())
+            .map(|spur| self.rodeo.resolve(spur));
+        closest_name(self.rodeo.resolve(&name), names).map(Into::into)
     }
 
     #[inline]
     fn get_function(&self, name: &str) -> Option<FunctionValue<'ctx>> {
-        // TODO: Keep a list of all method defined in the whole file.
-        self.module.get_function(name)
+        let spur = self.rodeo.get(name)?;
+        self.functions.get(&spur).copied()
     }
 
     /// Converts the given operator into a name that will be used for the function.
     #[inline]
-    fn unary_fn_name(&self, op: char) -> SmolStr {
+    fn unary_fn_name(&self, op: impl fmt::Display) -> SmolStr {
         format!("unary{}", op).into()
     }
 
     /// Converts the given operator into a name that will be used for the function.
     #[inline]
-    fn binary_fn_name(&self, op: char) -> SmolStr {
+    fn binary_fn_name(&self, op: impl fmt::Display) -> SmolStr {
         format!("binary{}", op).into()
     }
 
@@ -102,13 +350,28 @@ impl<'r, 'ctx> Compiler<'r, 'ctx> {
     fn compile_expr(&mut self, expr: &Expr) -> CompileResult<FloatValue<'ctx>> {
         match &expr.kind {
             ExprKind::Number(x) => Ok(self.ctx.f64_type().const_float(x.into_inner())),
-            ExprKind::Var(name) => match self.vars.get(&name.spur) {
+            ExprKind::Var(name) => match self.get_variable(name.spur) {
                 Some(var) => Ok(self
                     .builder
-                    .build_load(*var, self.rodeo.resolve(&name.spur))
+                    .build_load(var, self.rodeo.resolve(&name.spur))
                     .into_float_value()),
-                None => Err(expr.span.locate(self.file, CompileError::UnknownVariable)),
+                None => {
+                    let suggestion = self.suggest_variable(name.spur);
+                    Err(expr
+                        .span
+                        .locate(self.file, CompileError::UnknownVariable { suggestion }))
+                }
             },
+            ExprKind::Unary { op: '!', val } => {
+                let val = self.compile_expr(val)?;
+                let zero = self.ctx.f64_type().const_float(0.0);
+                let result =
+                    self.builder
+                        .build_float_compare(FloatPredicate::OEQ, val, zero, "nottemp");
+                Ok(self
+                    .builder
+                    .build_unsigned_int_to_float(result, self.ctx.f64_type(), "booltmp"))
+            }
             ExprKind::Unary { op, ref val } => {
                 let name = self.unary_fn_name(*op);
                 match self.get_function(&name) {
@@ -121,37 +384,73 @@ impl<'r, 'ctx> Compiler<'r, 'ctx> {
                             None => Err(expr.span.locate(self.file, CompileError::InvalidCall)),
                         }
                     }
-                    None => Err(expr.span.locate(self.file, CompileError::UnknownOperator)),
+                    None => {
+                        // The operator is always the first character of a unary expression.
+                        let span = Span::with_len(expr.span.start(), op.len_utf8());
+                        Err(span.locate(self.file, CompileError::UnknownOperator))
+                    }
                 }
             }
             ExprKind::Binary {
                 ref left,
                 op,
+                op_span,
                 ref right,
             } => {
-                let name = self.binary_fn_name(*op);
+                // Assignments are special, because the left side is not evaluated
+                if op == "=" {
+                    let var = match &left.kind {
+                        ExprKind::Var(name) => self.get_variable(name.spur).ok_or_else(|| {
+                            let suggestion = self.suggest_variable(name.spur);
+                            left.span
+                                .locate(self.file, CompileError::UnknownVariable { suggestion })
+                        })?,
+                        _ => {
+                            return Err(left
+                                .span
+                                .locate(self.file, CompileError::InvalidAssignment))
+                        }
+                    };
+
+                    let val = self.compile_expr(right)?;
+                    self.builder.build_store(var, val);
+                    return Ok(val);
+                }
+
+                if op == "&&" || op == "||" {
+                    return self.compile_logical(op == "&&", left, right);
+                }
+
+                // Constant divisors were already folded into a number by `compile_fun`.
+                let zero = matches!(right.kind, ExprKind::Number(x) if x.into_inner() == 0.0);
+                if op == "/" && zero {
+                    return Err(right
+                        .span
+                        .locate(self.file, CompileError::DivisionByZeroConstant));
+                }
+
+                let name = self.binary_fn_name(op);
                 let lhs = self.compile_expr(left)?;
                 let rhs = self.compile_expr(right)?;
 
-                match op {
-                    '+' => return Ok(self.builder.build_float_add(lhs, rhs, "addtemp")),
-                    '-' => return Ok(self.builder.build_float_sub(lhs, rhs, "subtemp")),
-                    '*' => return Ok(self.builder.build_float_mul(lhs, rhs, "multemp")),
-                    '<' => {
-                        let result = self.builder.build_float_compare(
-                            FloatPredicate::ULT,
-                            lhs,
-                            rhs,
-                            "cmptemp",
-                        );
-                        return Ok(self.builder.build_unsigned_int_to_float(
-                            result,
-                            self.ctx.f64_type(),
-                            "booltmp",
-                        ));
-                    }
+                match op.as_str() {
+                    "+" => return Ok(self.builder.build_float_add(lhs, rhs, "addtemp")),
+                    "-" => return Ok(self.builder.build_float_sub(lhs, rhs, "subtemp")),
+                    "*" => return Ok(self.builder.build_float_mul(lhs, rhs, "multemp")),
+                    "/" => return Ok(self.builder.build_float_div(lhs, rhs, "divtemp")),
                     _ => {}
-                };
+                }
+
+                if let Some(predicate) = comparison_predicate(op) {
+                    let result = self
+                        .builder
+                        .build_float_compare(predicate, lhs, rhs, "cmptemp");
+                    return Ok(self.builder.build_unsigned_int_to_float(
+                        result,
+                        self.ctx.f64_type(),
+                        "booltmp",
+                    ));
+                }
 
                 match self.get_function(&name) {
                     Some(fun) => {
@@ -163,16 +462,26 @@ impl<'r, 'ctx> Compiler<'r, 'ctx> {
                             None => Err(expr.span.locate(self.file, CompileError::InvalidCall)),
                         }
                     }
-                    None => Err(expr.span.locate(self.file, CompileError::UnknownOperator)),
+                    None => Err(op_span.locate(self.file, CompileError::UnknownOperator)),
                 }
             }
             ExprKind::Call { callee, ref args } => {
                 let fun = self
                     .get_function(self.rodeo.resolve(&callee.spur))
-                    .ok_or(expr.span.locate(self.file, CompileError::UnknownFunction))?;
+                    .ok_or_else(|| {
+                        let suggestion = closest_name(
+                            self.rodeo.resolve(&callee.spur),
+                            self.functions.keys().map(|spur| self.rodeo.resolve(spur)),
+                        )
+                        .map(Into::into);
+                        expr.span
+                            .locate(self.file, CompileError::UnknownFunction { suggestion })
+                    })?;
 
+                // Variadic functions accept more arguments than they declare.
                 let expected = fun.get_params().len();
-                if expected != args.len() {
+                let variadic = fun.get_type().is_var_arg();
+                if args.len() < expected || (!variadic && args.len() > expected) {
                     return Err(expr.span.locate(
                         self.file,
                         CompileError::InvalidArguments {
@@ -188,6 +497,10 @@ impl<'r, 'ctx> Compiler<'r, 'ctx> {
                     .collect::<CompileResult<Vec<_>>>()?;
 
                 let result = self.builder.build_call(fun, args.as_slice(), "calltemp");
+                // `void` functions have no result, so the call evaluates to `0`.
+                if fun.get_type().get_return_type().is_none() {
+                    return Ok(self.ctx.f64_type().const_float(0.0));
+                }
                 match result.try_as_basic_value().left() {
                     Some(val) => Ok(val.into_float_value()),
                     None => Err(expr.span.locate(self.file, CompileError::InvalidCall)),
@@ -198,14 +511,7 @@ impl<'r, 'ctx> Compiler<'r, 'ctx> {
                 ref then,
                 ref else_,
             } => {
-                // Compile condition and convert it to a boolean
-                let cond = self.compile_expr(cond)?;
-                let cond = self.builder.build_float_compare(
-                    FloatPredicate::ONE,
-                    cond,
-                    self.ctx.f64_type().const_float(0.0),
-                    "ifcond",
-                );
+                let cond = self.compile_cond(cond, "ifcond")?;
                 // Get the current function
                 let fun = self
                     .builder
@@ -232,7 +538,10 @@ impl<'r, 'ctx> Compiler<'r, 'ctx> {
 
                 // Build else block
                 self.builder.position_at_end(else_block);
-                let else_ = self.compile_expr(else_)?;
+                let else_ = match else_ {
+                    Some(else_) => self.compile_expr(else_)?,
+                    None => self.ctx.f64_type().const_float(0.0),
+                };
                 self.builder.build_unconditional_branch(merge_block);
 
                 let else_block = self.builder.get_insert_block().unwrap();
@@ -244,7 +553,98 @@ impl<'r, 'ctx> Compiler<'r, 'ctx> {
                 phi.add_incoming(&[(&then, then_block), (&else_, else_block)]);
                 Ok(phi.as_basic_value().into_float_value())
             }
-            ExprKind::For { .. } => todo!(),
+            ExprKind::For {
+                var,
+                ref start,
+                ref end,
+                ref step,
+                ref body,
+            } => {
+                let spur = var.spur;
+                let rodeo = Arc::clone(&self.rodeo);
+                let name = rodeo.resolve(&spur);
+                let fun = self
+                    .builder
+                    .get_insert_block()
+                    .unwrap()
+                    .get_parent()
+                    .unwrap();
+
+                // Allocate the loop variable and store the start value
+                let alloca = self.create_entry_block_alloca(fun, name);
+                let start = self.compile_expr(start)?;
+                self.builder.build_store(alloca, start);
+
+                // Jump into the loop body
+                let loop_block = self.ctx.append_basic_block(fun, "loop");
+                self.builder.build_unconditional_branch(loop_block);
+                self.builder.position_at_end(loop_block);
+
+                // Shadow any outer variable with the same name
+                let old = self.vars.remove(&spur);
+                self.vars.insert(spur, alloca);
+
+                self.compile_expr(body)?;
+
+                let step = match step {
+                    Some(step) => self.compile_expr(step)?,
+                    None => self.ctx.f64_type().const_float(1.0),
+                };
+
+                // Evaluate the end condition before incrementing the variable
+                let end = self.compile_cond(end, "loopcond")?;
+
+                let current = self.builder.build_load(alloca, name).into_float_value();
+                let next = self.builder.build_float_add(current, step, "nextvar");
+                self.builder.build_store(alloca, next);
+
+                let after_block = self.ctx.append_basic_block(fun, "afterloop");
+                self.builder
+                    .build_conditional_branch(end, loop_block, after_block);
+                self.builder.position_at_end(after_block);
+
+                // Restore the shadowed variable
+                self.vars.remove(&spur);
+                if let Some(old) = old {
+                    self.vars.insert(spur, old);
+                }
+
+                Ok(self.ctx.f64_type().const_float(0.0))
+            }
+            ExprKind::While { ref cond, ref body } => {
+                let fun = self
+                    .builder
+                    .get_insert_block()
+                    .unwrap()
+                    .get_parent()
+                    .unwrap();
+
+                let cond_block = self.ctx.append_basic_block(fun, "whilecond");
+                let loop_block = self.ctx.append_basic_block(fun, "loop");
+                let after_block = self.ctx.append_basic_block(fun, "afterloop");
+
+                // The condition is re-evaluated before every iteration
+                self.builder.build_unconditional_branch(cond_block);
+                self.builder.position_at_end(cond_block);
+                let cond = self.compile_cond(cond, "loopcond")?;
+                self.builder
+                    .build_conditional_branch(cond, loop_block, after_block);
+
+                // Build the loop body and jump back to the condition
+                self.builder.position_at_end(loop_block);
+                self.compile_expr(body)?;
+                self.builder.build_unconditional_branch(cond_block);
+
+                self.builder.position_at_end(after_block);
+                Ok(self.ctx.f64_type().const_float(0.0))
+            }
+            ExprKind::Block { ref exprs } => {
+                let mut value = self.ctx.f64_type().const_float(0.0);
+                for expr in exprs {
+                    value = self.compile_expr(expr)?;
+                }
+                Ok(value)
+            }
             ExprKind::Let { ref vars, body } => {
                 let mut old = HashMap::new();
 
@@ -282,22 +682,105 @@ impl<'r, 'ctx> Compiler<'r, 'ctx> {
         }
     }
 
+    /// Compiles the expression as a condition and returns it as a boolean.
+    ///
+    /// Builtin comparisons are used directly, instead of converting
+    /// their result to a float and back.
+    fn compile_cond(&mut self, expr: &Expr, name: &str) -> CompileResult<IntValue<'ctx>> {
+        if let ExprKind::Binary {
+            left, op, right, ..
+        } = &expr.kind
+        {
+            if let Some(predicate) = comparison_predicate(op) {
+                let lhs = self.compile_expr(left)?;
+                let rhs = self.compile_expr(right)?;
+                return Ok(self.builder.build_float_compare(predicate, lhs, rhs, name));
+            }
+        }
+
+        let cond = self.compile_expr(expr)?;
+        Ok(self.builder.build_float_compare(
+            FloatPredicate::ONE,
+            cond,
+            self.ctx.f64_type().const_float(0.0),
+            name,
+        ))
+    }
+
+    /// Compiles `&&` or `||`, which only evaluate the right side
+    /// if the left side doesn't decide the result.
+    ///
+    /// The result is `1` if the expression is true, and `0` otherwise.
+    fn compile_logical(
+        &mut self,
+        is_and: bool,
+        left: &Expr,
+        right: &Expr,
+    ) -> CompileResult<FloatValue<'ctx>> {
+        let lhs = self.compile_cond(left, "lhscond")?;
+        let lhs_block = self.builder.get_insert_block().unwrap();
+        let fun = lhs_block.get_parent().unwrap();
+
+        let rhs_block = self.ctx.append_basic_block(fun, "rhs");
+        let merge_block = self.ctx.append_basic_block(fun, "logicalcont");
+        if is_and {
+            self.builder
+                .build_conditional_branch(lhs, rhs_block, merge_block);
+        } else {
+            self.builder
+                .build_conditional_branch(lhs, merge_block, rhs_block);
+        }
+
+        self.builder.position_at_end(rhs_block);
+        let rhs = self.compile_cond(right, "rhscond")?;
+        self.builder.build_unconditional_branch(merge_block);
+        let rhs_block = self.builder.get_insert_block().unwrap();
+
+        // If the right side is skipped, `&&` is false and `||` is true.
+        self.builder.position_at_end(merge_block);
+        let bool_ty = self.ctx.bool_type();
+        let skipped = bool_ty.const_int((!is_and).into(), false);
+        let phi = self.builder.build_phi(bool_ty, "logicaltemp");
+        phi.add_incoming(&[(&skipped, lhs_block), (&rhs, rhs_block)]);
+
+        Ok(self.builder.build_unsigned_int_to_float(
+            phi.as_basic_value().into_int_value(),
+            self.ctx.f64_type(),
+            "booltmp",
+        ))
+    }
+
     fn compile_proto(
         &mut self,
         name: Spur,
         proto_args: &Vec<Identifier>,
+        variadic: bool,
+        ret: ReturnType,
     ) -> CompileResult<FunctionValue<'ctx>> {
-        let ret_ty = self.ctx.f64_type();
+        let f64_ty = self.ctx.f64_type();
 
-        let args = std::iter::repeat(ret_ty)
+        let args = std::iter::repeat(f64_ty)
             .take(proto_args.len())
             .map(|ty| ty.into())
             .collect::<Vec<BasicTypeEnum<'_>>>();
 
-        let fun_ty = self.ctx.f64_type().fn_type(args.as_slice(), false);
-        let fun = self
-            .module
-            .add_function(self.rodeo.resolve(&name), fun_ty, None);
+        // Reuse the prototype if the function was already declared, but not defined yet.
+        let fun = match self.functions.get(&name) {
+            Some(fun)
+                if fun.count_basic_blocks() == 0 && fun.count_params() as usize == args.len() =>
+            {
+                *fun
+            }
+            _ => {
+                let fun_ty = match ret {
+                    ReturnType::Double => f64_ty.fn_type(args.as_slice(), variadic),
+                    ReturnType::Void => self.ctx.void_type().fn_type(args.as_slice(), variadic),
+                };
+                self.module
+                    .add_function(self.rodeo.resolve(&name), fun_ty, None)
+            }
+        };
+        self.functions.insert(name, fun);
 
         for (arg, Identifier { spur, .. }) in fun.get_param_iter().zip(proto_args) {
             arg.into_float_value().set_name(self.rodeo.resolve(&spur));
@@ -306,6 +789,20 @@ impl<'r, 'ctx> Compiler<'r, 'ctx> {
         Ok(fun)
     }
 
+    /// Declares an external function, which uses the C calling convention.
+    fn compile_extern(
+        &mut self,
+        name: Spur,
+        args: &Vec<Identifier>,
+        variadic: bool,
+        ret: ReturnType,
+    ) -> CompileResult<FunctionValue<'ctx>> {
+        let fun = self.compile_proto(name, args, variadic, ret)?;
+        fun.set_linkage(Linkage::External);
+        fun.set_call_conventions(C_CALL_CONV);
+        Ok(fun)
+    }
+
     fn compile_fun(
         &mut self,
         span: Span,
@@ -313,11 +810,21 @@ impl<'r, 'ctx> Compiler<'r, 'ctx> {
         args: &Vec<Identifier>,
         body: &Expr,
     ) -> CompileResult<FunctionValue<'ctx>> {
-        let fun = self.compile_proto(name, args)?;
+        // A function that already has a body was defined by an earlier item.
+        if let Some(fun) = self.functions.get(&name) {
+            if fun.count_basic_blocks() > 0 {
+                let name = self.rodeo.resolve(&name).into();
+                return Err(span.locate(self.file, CompileError::Redefinition { name }));
+            }
+        }
+
+        let fun = self.compile_proto(name, args, false, ReturnType::Double)?;
         let entry = self.ctx.append_basic_block(fun, "entry");
 
         self.builder.position_at_end(entry);
 
+        // Variables of other functions are not visible.
+        self.vars.clear();
         self.vars.reserve(args.len());
         for (arg, Identifier { spur, .. }) in fun.get_param_iter().zip(args) {
             let name = self.rodeo.resolve(&spur);
@@ -326,24 +833,149 @@ impl<'r, 'ctx> Compiler<'r, 'ctx> {
             self.vars.insert(spur.clone(), alloca);
         }
 
-        let body = self.compile_expr(body)?;
+        let mut body = body.clone();
+        fold_constants(&mut body);
+        let body = self.compile_expr(&body)?;
         self.builder.build_return(Some(&body));
 
         if fun.verify(true) {
             self.fpm.run_on(&fun);
             Ok(fun)
         } else {
+            self.functions.remove(&name);
             unsafe { fun.delete() }
             Err(span.locate(self.file, CompileError::InvalidFunctionGenerated))
         }
     }
 
-    pub fn compile_item(&mut self, item: &Item) -> CompileResult<FunctionValue<'ctx>> {
+    /// Compiles all given items.
+    ///
+    /// The prototypes of all items are declared before any body is compiled,
+    /// so functions can be called before they are defined.
+    pub fn compile_items(&mut self, items: &[Item]) -> CompileResult<()> {
+        for item in items {
+            self.declare_item(item)?;
+        }
+        for item in items {
+            self.compile_item(item)?;
+        }
+        self.verify_module()
+    }
+
+    /// Compiles the items of multiple files into the same module.
+    ///
+    /// Like in `compile_items`, all items of all files are declared first,
+    /// so files can call functions of files that come later.
+    pub fn compile_files(&mut self, files: &[(FileId, Arc<Vec<Item>>)]) -> CompileResult<()> {
+        let file = self.file;
+        let result = self.compile_files_inner(files);
+        self.file = file;
+        result
+    }
+
+    fn compile_files_inner(&mut self, files: &[(FileId, Arc<Vec<Item>>)]) -> CompileResult<()> {
+        for (file, items) in files {
+            self.file = *file;
+            for item in items.iter() {
+                self.declare_item(item)?;
+            }
+        }
+        for (file, items) in files {
+            self.file = *file;
+            for item in items.iter() {
+                self.compile_item(item)?;
+            }
+        }
+        self.verify_module()
+    }
+
+    /// Runs the LLVM verifier on the whole module.
+    ///
+    /// Functions are already verified while they are compiled,
+    /// but this also catches errors between functions.
+    pub fn verify_module(&self) -> CompileResult<()> {
+        self.module.verify().map_err(|err| {
+            let message = err.to_string().trim_end().to_string();
+            Span::default().locate(self.file, CompileError::InvalidModule { message })
+        })
+    }
+
+    /// Compiles all given items and returns the IR of the whole module.
+    pub fn compile_to_ir_string(&mut self, items: &[Item]) -> CompileResult<String> {
+        self.compile_items(items)?;
+        Ok(self.module.print_to_string().to_string())
+    }
+
+    /// Declares the prototype of the given item without compiling its body.
+    ///
+    /// Globals are fully defined here, because their initializer is constant.
+    pub fn declare_item(&mut self, item: &Item) -> CompileResult<()> {
+        match &item.kind {
+            ItemKind::Function { name, args, .. } => self
+                .compile_proto(name.spur, args, false, ReturnType::Double)
+                .map(drop),
+            ItemKind::Extern {
+                name,
+                args,
+                variadic,
+                ret,
+            } => self
+                .compile_extern(name.spur, args, *variadic, *ret)
+                .map(drop),
+            // Imports are resolved before codegen, see `compile_files`.
+            ItemKind::Import { .. } => Ok(()),
+            ItemKind::Global { name, init } => {
+                let value = match init {
+                    Some(init) => fold_constant(init).ok_or_else(|| {
+                        init.span
+                            .locate(self.file, CompileError::NonConstantInitializer)
+                    })?,
+                    None => NotNan::default(),
+                };
+
+                let ty = self.ctx.f64_type();
+                let global = self
+                    .module
+                    .add_global(ty, None, self.rodeo.resolve(&name.spur));
+                global.set_initializer(&ty.const_float(value.into_inner()));
+                self.globals.insert(name.spur, global.as_pointer_value());
+                Ok(())
+            }
+            ItemKind::Operator {
+                op,
+                is_binary,
+                args,
+                ..
+            } => {
+                let name = if *is_binary {
+                    self.binary_fn_name(op)
+                } else {
+                    self.unary_fn_name(op)
+                };
+                let name = self.rodeo.get_or_intern(name.as_str());
+                self.compile_proto(name, args, false, ReturnType::Double)
+                    .map(drop)
+            }
+        }
+    }
+
+    /// Compiles the given item.
+    ///
+    /// The item must be declared using `declare_item` before.
+    pub fn compile_item(&mut self, item: &Item) -> CompileResult<()> {
         match &item.kind {
             ItemKind::Function { name, args, body } => {
-                self.compile_fun(item.span, name.spur, args, body)
+                self.compile_fun(item.span, name.spur, args, body).map(drop)
             }
-            ItemKind::Extern { name, args } => self.compile_proto(name.spur, args),
+            ItemKind::Extern {
+                name,
+                args,
+                variadic,
+                ret,
+            } => self
+                .compile_extern(name.spur, args, *variadic, *ret)
+                .map(drop),
+            ItemKind::Global { .. } | ItemKind::Import { .. } => Ok(()),
             ItemKind::Operator {
                 op,
                 is_binary,
@@ -362,7 +994,537 @@ impl<'r, 'ctx> Compiler<'r, 'ctx> {
                     args,
                     body,
                 )
+                .map(drop)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        parse::{FrontendDatabaseStorage, Parser},
+        source::{File, SourceDatabase, SourceDatabaseStorage},
+        span::Locatable,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A database that counts how many queries were executed.
+    #[salsa::database(SourceDatabaseStorage, FrontendDatabaseStorage, CodegenDatabaseStorage)]
+    #[derive(Default)]
+    struct CountingDatabase {
+        storage: salsa::Storage<Self>,
+        executed: AtomicUsize,
+    }
+
+    impl salsa::Database for CountingDatabase {
+        fn salsa_event(&self, event: salsa::Event) {
+            if let salsa::EventKind::WillExecute { .. } = event.kind {
+                self.executed.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Parses the code and passes its items to `f`, together with a compiler
+    /// that didn't compile anything yet and the module it compiles into.
+    fn with_items<T>(
+        code: &str,
+        level: OptimizationLevel,
+        f: impl FnOnce(&[Item], &mut Compiler<'_, '_>, &Module<'_>) -> T,
+    ) -> T {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let items = Parser::new(Arc::clone(&rodeo), code, FileId::default())
+            .parse()
+            .unwrap();
+
+        let ctx = Context::create();
+        let builder = ctx.create_builder();
+        let module = ctx.create_module("test");
+        let fpm = create_pass_manager(&module, level);
+
+        let mut compiler = Compiler::new(FileId::default(), &ctx, &builder, &fpm, &module, rodeo);
+        f(&items, &mut compiler, &module)
+    }
+
+    fn with_compiler<T>(
+        code: &str,
+        level: OptimizationLevel,
+        f: impl FnOnce(&Compiler<'_, '_>, &Module<'_>) -> T,
+    ) -> T {
+        with_items(code, level, |items, compiler, module| {
+            compiler.compile_items(items).unwrap();
+            f(compiler, module)
+        })
+    }
+
+    fn run(code: &str) -> Option<f64> {
+        with_compiler(code, OptimizationLevel::None, |compiler, _| {
+            compiler.run_main(OptimizationLevel::None).ok()
+        })
+    }
+
+    fn compile_error(code: &str) -> CompileError {
+        compile_error_at(code).data().clone()
+    }
+
+    fn compile_error_at(code: &str) -> Locatable<CompileError> {
+        with_items(code, OptimizationLevel::None, |items, compiler, _| {
+            compiler.compile_items(items).unwrap_err()
+        })
+    }
+
+    fn ir(code: &str, level: OptimizationLevel) -> String {
+        with_compiler(code, level, |_, module| {
+            module.print_to_string().to_string()
+        })
+    }
+
+    #[test]
+    fn compile_to_ir_string() {
+        let code = "def add(a b) a + b; def f(x) y;";
+        with_items(code, OptimizationLevel::None, |items, compiler, _| {
+            let ir = compiler.compile_to_ir_string(&items[..1]).unwrap();
+            assert!(
+                ir.contains("define double @add(double %a, double %b)"),
+                "{}",
+                ir
+            );
+
+            let err = compiler.compile_to_ir_string(&items[1..]).unwrap_err();
+            assert_eq!(
+                err.data(),
+                &CompileError::UnknownVariable { suggestion: None }
+            );
+        });
+    }
+
+    #[test]
+    fn compile_ir_query() {
+        let mut db = CountingDatabase::default();
+        db.set_rodeo(Arc::new(Default::default()));
+        db.set_predefined_operators(Arc::new(Vec::new()));
+        let file = File::new(
+            Arc::new("test.kl".into()),
+            Arc::new("def f(x) x + 1;".into()),
+        );
+        let file = db.intern_file(file);
+
+        let ir = db.compile_ir(file).unwrap();
+        assert!(ir.contains("define double @f(double %x)"), "{}", ir);
+        let executed = db.executed.load(Ordering::SeqCst);
+        assert!(executed > 0);
+
+        // The unchanged file is not compiled again.
+        assert!(Arc::ptr_eq(&ir, &db.compile_ir(file).unwrap()));
+        assert_eq!(db.executed.load(Ordering::SeqCst), executed);
+
+        let file = File::new(Arc::new("err.kl".into()), Arc::new("def f() y;".into()));
+        let err = db.compile_ir(db.intern_file(file)).unwrap_err();
+        assert_eq!(err.message, "unknown variable");
+    }
+
+    #[test]
+    fn compile_for() {
+        let result = run("extern putchard(x); for i = 1.0, i < 5.0 in putchard(i)");
+        assert_eq!(result, Some(0.0));
+    }
+
+    #[test]
+    fn compile_if_without_else() {
+        assert_eq!(run("def f(x) if x < 1 then 5; f(0) + f(2)"), Some(5.0));
+        assert_eq!(
+            run("def f(x) if x then if x > 1 then 2 else 3; f(0)"),
+            Some(0.0)
+        );
+    }
+
+    #[test]
+    fn compile_while() {
+        assert_eq!(run("def f(x) while x > 1 in x; f(0)"), Some(0.0));
+    }
+
+    #[test]
+    fn optimize_function() {
+        let unoptimized = ir("def f(x) var y = 2 in x * y;", OptimizationLevel::None);
+        assert!(unoptimized.contains("alloca"), "{}", unoptimized);
+
+        let optimized = ir("def f(x) var y = 2 in x * y;", OptimizationLevel::Less);
+        assert!(!optimized.contains("alloca"), "{}", optimized);
+
+        let folded = ir("def f() 1+2;", OptimizationLevel::Less);
+        assert!(folded.contains("ret double 3.000000e+00"), "{}", folded);
+    }
+
+    #[test]
+    fn parse_opt_levels() {
+        assert_eq!(parse_opt_level("0"), Ok(OptimizationLevel::None));
+        assert_eq!(parse_opt_level("3"), Ok(OptimizationLevel::Aggressive));
+        assert!(parse_opt_level("4").is_err());
+    }
+
+    #[test]
+    fn compile_forward_reference() {
+        assert_eq!(run("def a() b(); def b() 1; a()"), Some(1.0));
+    }
+
+    #[test]
+    fn compile_assignment() {
+        let code = "def sum(n) var i = 0, s = 0 in
+            (while i < n in (s = s + i) + (i = i + 1)) + s;
+            sum(5)";
+        assert_eq!(run(code), Some(10.0));
+    }
+
+    #[test]
+    fn suggest_similar_names() {
+        assert_eq!(
+            compile_error("def foo() 1; def f() fooo();"),
+            CompileError::UnknownFunction {
+                suggestion: Some("foo".into())
+            }
+        );
+        assert_eq!(
+            compile_error("def f(value) vale = 2;"),
+            CompileError::UnknownVariable {
+                suggestion: Some("value".into())
+            }
+        );
+    }
+
+    #[test]
+    fn compile_invalid_assignment() {
+        assert_eq!(
+            compile_error("def f() 1 = 2;"),
+            CompileError::InvalidAssignment
+        );
+        assert_eq!(
+            compile_error("def f() x = 2;"),
+            CompileError::UnknownVariable { suggestion: None }
+        );
+    }
+
+    #[test]
+    fn call_builtin() {
+        assert_eq!(run("extern putchard(x); putchard(65);"), Some(65.0));
+        assert_eq!(run("extern printd(x); printd(4.5);"), Some(4.5));
+    }
+
+    #[test]
+    fn comparison_condition_without_conversion() {
+        let code = ir(
+            "def f(a b) if a < b then 1 else 2;",
+            OptimizationLevel::None,
+        );
+        assert!(code.contains("fcmp ult"), "{}", code);
+        assert!(!code.contains("uitofp"), "{}", code);
+        assert!(!code.contains("fcmp one"), "{}", code);
+
+        let code = ir(
+            "def f(n) while n > 0 in n = n - 1;",
+            OptimizationLevel::None,
+        );
+        assert!(!code.contains("uitofp"), "{}", code);
+
+        assert_eq!(run("def f(a b) if a < b then 1 else 2; f(1, 2)"), Some(1.0));
+        assert_eq!(run("def f(a b) if a < b then 1 else 2; f(2, 1)"), Some(2.0));
+    }
+
+    #[test]
+    fn compile_block() {
+        assert_eq!(run("def f(x) { x = x + 1; x * 2 }; f(3)"), Some(8.0));
+        assert_eq!(run("def f() {}; f()"), Some(0.0));
+    }
+
+    #[test]
+    fn verify_module() {
+        let err = with_compiler(
+            "def f(x) x;",
+            OptimizationLevel::None,
+            |compiler, module| {
+                assert_eq!(compiler.verify_module(), Ok(()));
+
+                // A block without a terminator is invalid.
+                let ctx = compiler.ctx;
+                let broken =
+                    module.add_function("broken", ctx.f64_type().fn_type(&[], false), None);
+                ctx.append_basic_block(broken, "entry");
+
+                compiler.verify_module().unwrap_err()
+            },
+        );
+
+        match err.data() {
+            CompileError::InvalidModule { message } => {
+                assert!(message.contains("broken"), "{}", message)
+            }
+            err => panic!("expected invalid module, found {:?}", err),
+        }
+
+        let diagnostic: Diagnostic = err.into();
+        assert_eq!(diagnostic.message, "invalid module generated");
+        assert!(diagnostic.labels.is_empty());
+    }
+
+    #[test]
+    fn compile_logical_operators() {
+        assert_eq!(run("def f(a b) a && b; f(2, 3)"), Some(1.0));
+        assert_eq!(run("def f(a b) a && b; f(2, 0)"), Some(0.0));
+        assert_eq!(run("def f(a b) a || b; f(0, 5)"), Some(1.0));
+        assert_eq!(run("def f(a b) a || b; f(0, 0)"), Some(0.0));
+
+        // `&&` binds stronger than `||`, and both are weaker than comparisons.
+        assert_eq!(run("def f(a) a < 2 && a > 0; f(1)"), Some(1.0));
+        assert_eq!(run("def f(a) 0 || a && 0; f(1)"), Some(0.0));
+        assert_eq!(run("1 || 0 && 0"), Some(1.0));
+    }
+
+    #[test]
+    fn compile_not() {
+        assert_eq!(run("!0"), Some(1.0));
+        assert_eq!(run("!5"), Some(0.0));
+        assert_eq!(run("def f(x) !x; f(0)"), Some(1.0));
+        assert_eq!(run("def f(x) !x; f(5)"), Some(0.0));
+        assert_eq!(run("def f(a b) !a && !b; f(0, 0)"), Some(1.0));
+        assert_eq!(run("def f(a b) !(a || b); f(0, 1)"), Some(0.0));
+
+        let code = ir("def f(x) !x;", OptimizationLevel::None);
+        assert!(code.contains("fcmp oeq double %x"), "{}", code);
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        let counter = "var count; def inc() count = count + 1;";
+        let run_counter = |code: &str| run(&format!("{} {}", counter, code));
+
+        assert_eq!(
+            run_counter("def f(a) { a && inc(); count }; f(0)"),
+            Some(0.0)
+        );
+        assert_eq!(
+            run_counter("def f(a) { a && inc(); count }; f(1)"),
+            Some(1.0)
+        );
+        assert_eq!(
+            run_counter("def f(a) { a || inc(); count }; f(1)"),
+            Some(0.0)
+        );
+        assert_eq!(
+            run_counter("def f(a) { a || inc(); count }; f(0)"),
+            Some(1.0)
+        );
+
+        let code = ir("def f(a b) a && b;", OptimizationLevel::None);
+        assert!(code.contains("phi i1"), "{}", code);
+        assert!(!code.contains(" and "), "{}", code);
+    }
+
+    #[test]
+    fn fold_constant_expressions() {
+        let code = ir("def f() 2 * 3 + 1;", OptimizationLevel::None);
+        assert!(code.contains("ret double 7.000000e+00"), "{}", code);
+        assert!(!code.contains("fmul"), "{}", code);
+        assert!(!code.contains("fadd"), "{}", code);
+
+        assert_eq!(run("(1 + 1 < 3) + (4 / 2 == 2)"), Some(2.0));
+        // `0 / 0` is not folded, because it would produce `NaN`.
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let expr = Parser::new(rodeo, "0 / 0", FileId::default())
+            .parse_expr()
+            .unwrap();
+        assert_eq!(fold_constant(&expr), None);
+    }
+
+    #[test]
+    fn fold_nested_constants() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let mut expr = Parser::new(
+            Arc::clone(&rodeo),
+            "x * (2 * 3 + 1) + !(0 / 0)",
+            FileId::default(),
+        )
+        .parse_expr()
+        .unwrap();
+        fold_constants(&mut expr);
+
+        let expected = Parser::new(rodeo, "x * 7 + !(0 / 0)", FileId::default())
+            .parse_expr()
+            .unwrap();
+        assert!(expr.eq_ignore_span(&expected), "{:?}", expr);
+    }
+
+    #[test]
+    fn division_by_constant_zero() {
+        let err = compile_error_at("def f(x) x + 1 / (2 - 2);");
+        assert_eq!(err.data(), &CompileError::DivisionByZeroConstant);
+        assert_eq!(err.span(), Span::new(17, 24));
+
+        assert_eq!(compile_error("1 / 0"), CompileError::DivisionByZeroConstant);
+        assert_eq!(run("def f(x) 1 / x; f(4)"), Some(0.25));
+    }
+
+    #[test]
+    fn function_redefinition() {
+        let err = compile_error_at("def f(x) x; def f(x) x + 1;");
+        assert_eq!(err.data(), &CompileError::Redefinition { name: "f".into() });
+        assert_eq!(err.span(), Span::new(12, 27));
+
+        let err = compile_error_at("1; 2");
+        assert_eq!(
+            err.data(),
+            &CompileError::Redefinition {
+                name: "main".into()
+            }
+        );
+        assert_eq!(err.span(), Span::new(3, 4));
+
+        let diagnostic: Diagnostic = err.into();
+        assert_eq!(diagnostic.message, "`main` is defined multiple times");
+        assert_eq!(diagnostic.notes.len(), 1);
+    }
+
+    #[test]
+    fn unknown_unary_operator() {
+        let err = compile_error_at("def f(x) 1 + ~x;");
+        assert_eq!(err.data(), &CompileError::UnknownOperator);
+        assert_eq!(err.span(), Span::new(13, 14));
+    }
+
+    #[test]
+    fn unknown_binary_operator() {
+        let code = "def binary | 5 (a b) a; def f(x) 1 + x | 2;";
+        // Only compile the function, so the operator is never defined.
+        let err = with_items(code, OptimizationLevel::None, |items, compiler, _| {
+            compiler.compile_items(&items[1..]).unwrap_err()
+        });
+        assert_eq!(err.data(), &CompileError::UnknownOperator);
+        assert_eq!(err.span(), Span::new(39, 40));
+    }
+
+    #[test]
+    fn llvm_version() {
+        let version = Compiler::llvm_version();
+        assert!(!version.is_empty());
+        assert!(version.split('.').all(|part| part.parse::<u32>().is_ok()));
+    }
+
+    #[test]
+    fn run_without_main() {
+        let result = with_compiler("def foo() 1;", OptimizationLevel::None, |compiler, _| {
+            compiler.run_main(OptimizationLevel::None)
+        });
+        assert_eq!(result, Err(RunError::NoMain));
+    }
+
+    #[test]
+    fn call_extern() {
+        assert_eq!(run("extern cos(x); cos(0)"), Some(1.0));
+        assert_eq!(run("extern sin(x); sin(0)"), Some(0.0));
+
+        let code = ir("extern cos(x);", OptimizationLevel::None);
+        assert!(code.contains("declare double @cos(double)"), "{}", code);
+    }
+
+    #[test]
+    fn call_variadic_extern() {
+        let code = ir(
+            "extern printd(x ...); def f() printd(1) + printd(1, 2, 3);",
+            OptimizationLevel::None,
+        );
+        assert!(
+            code.contains("declare double @printd(double, ...)"),
+            "{}",
+            code
+        );
+        assert!(code.contains("@printd(double 1.000000e+00)"), "{}", code);
+        assert!(
+            code.contains("@printd(double 1.000000e+00, double 2.000000e+00, double 3.000000e+00)"),
+            "{}",
+            code
+        );
+
+        assert_eq!(
+            compile_error("extern printd(x ...); def f() printd();"),
+            CompileError::InvalidArguments {
+                expected: 1,
+                found: 0
+            }
+        );
+    }
+
+    #[test]
+    fn call_void_extern() {
+        let code = ir(
+            "extern putchard(x) -> void; def f() putchard(65) + 1;",
+            OptimizationLevel::None,
+        );
+        assert!(code.contains("declare void @putchard(double)"), "{}", code);
+        assert!(
+            code.contains("call void @putchard(double 6.500000e+01)"),
+            "{}",
+            code
+        );
+        assert!(code.contains("ret double 1.000000e+00"), "{}", code);
+    }
+
+    #[test]
+    fn compile_recursion() {
+        let code = "def fib(n) if n < 2 then n else fib(n - 1) + fib(n - 2); fib(10)";
+        assert_eq!(run(code), Some(55.0));
+
+        let code = "
+            def even(n) if n == 0 then 1 else odd(n - 1);
+            def odd(n) if n == 0 then 0 else even(n - 1);
+            even(10) + odd(7)";
+        assert_eq!(run(code), Some(2.0));
+    }
+
+    #[test]
+    fn compile_global() {
+        let code = ir("var half = 1 / 2; var zero;", OptimizationLevel::None);
+        assert!(
+            code.contains("@half = global double 5.000000e-01"),
+            "{}",
+            code
+        );
+        assert!(
+            code.contains("@zero = global double 0.000000e+00"),
+            "{}",
+            code
+        );
+
+        let code = "var count = 1; def inc() count = count + 1; inc() + inc()";
+        assert_eq!(run(code), Some(5.0));
+        assert_eq!(run("def f(x) x * scale; var scale = 3; f(2)"), Some(6.0));
+    }
+
+    #[test]
+    fn compile_non_constant_global() {
+        let err = compile_error("def f() 1; var x = f();");
+        assert_eq!(err, CompileError::NonConstantInitializer);
+    }
+
+    #[test]
+    fn compile_division() {
+        let result = run("def f(a b) a / b; f(10, 4)");
+        assert_eq!(result, Some(2.5));
+    }
+
+    #[test]
+    fn compile_greater_than() {
+        assert_eq!(run("def f(a b) a > b; f(2, 1)"), Some(1.0));
+        assert_eq!(run("def f(a b) a > b; f(1, 1)"), Some(0.0));
+    }
+
+    #[test]
+    fn compile_compound_comparisons() {
+        assert_eq!(run("def f(a b) a <= b; f(1, 1)"), Some(1.0));
+        assert_eq!(run("def f(a b) a <= b; f(2, 1)"), Some(0.0));
+        assert_eq!(run("def f(a b) a >= b; f(1, 1)"), Some(1.0));
+        assert_eq!(run("def f(a b) a >= b; f(1, 2)"), Some(0.0));
+        assert_eq!(run("def f(a b) a == b; f(3, 3)"), Some(1.0));
+        assert_eq!(run("def f(a b) a == b; f(3, 4)"), Some(0.0));
+        assert_eq!(run("def f(a b) a != b; f(3, 4)"), Some(1.0));
+        assert_eq!(run("def f(a b) a != b; f(3, 3)"), Some(0.0));
+    }
+}
@@ -0,0 +1,221 @@
+//! The compiler driver, which turns a source file into an object file.
+
+use crate::Args;
+use inkwell::{
+    context::Context,
+    module::Module,
+    targets::{
+        CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine, TargetTriple,
+    },
+};
+use kaleidoscope::{
+    analysis::resolve_imported_names,
+    codegen::{create_pass_manager, Compiler},
+    error::{self, ErrorFormat},
+    parse::FrontendDatabase,
+    pretty::Pretty,
+    source::{File, FileId},
+    CompilerDatabase, Diagnostic, SourceDatabase,
+};
+use std::{
+    io::{self, Read, Write},
+    path::Path,
+    sync::Arc,
+};
+
+/// The message that is returned if compilation failed because of an
+/// already emitted diagnostic.
+pub const ABORT_MESSAGE: &str = "aborting due to previous error";
+
+/// The width that is used to pretty print the AST.
+const PRETTY_WIDTH: usize = 50;
+
+/// Compiles the file at `path` and writes the resulting object file
+/// to the output specified in `args`.
+pub fn compile_file(args: &Args, path: &Path) -> Result<(), String> {
+    let db = database();
+    let file = db
+        .load_file(path)
+        .map_err(|err| format!("failed to read '{}': {}", path.display(), err))?;
+    compile(args, db, file)
+}
+
+/// Reads the whole program from stdin and compiles it like a file named `<stdin>`.
+pub fn compile_stdin(args: &Args) -> Result<(), String> {
+    let mut source = String::new();
+    io::stdin()
+        .read_to_string(&mut source)
+        .map_err(|err| format!("failed to read stdin: {}", err))?;
+
+    let db = database();
+    let file = db.add_file(File::new(Arc::new("<stdin>".into()), Arc::new(source)));
+    compile(args, db, file)
+}
+
+fn database() -> CompilerDatabase {
+    let mut db = CompilerDatabase::default();
+    db.set_rodeo(Arc::new(Default::default()));
+    db.set_predefined_operators(Arc::new(Vec::new()));
+    db
+}
+
+/// Compiles the given file and writes the resulting object file
+/// to the output specified in `args`.
+fn compile(args: &Args, db: CompilerDatabase, file: FileId) -> Result<(), String> {
+    if args.emit_lex {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        // Positions are printed 1-based, like in diagnostics.
+        let position = |offset| {
+            let (line, column) = db
+                .line_column(file, offset)
+                .expect("token offsets are always inside the file");
+            format!("{}:{}", line + 1, column + 1)
+        };
+        for token in db.tokens(file).iter() {
+            writeln!(
+                stdout,
+                "{:?} @ {}-{} '{}'",
+                token.kind,
+                position(token.span.start()),
+                position(token.span.end()),
+                token.slice,
+            )
+            .map_err(|err| format!("failed to print tokens: {}", err))?;
+        }
+    }
+
+    let items = match db.parse(file) {
+        Ok(items) => items,
+        Err(err) => {
+            emit(args, &db, err.into());
+            return Err(ABORT_MESSAGE.into());
+        }
+    };
+
+    let files = match db.load_imports(file) {
+        Ok(files) => files
+            .into_iter()
+            .map(|file| {
+                (
+                    file,
+                    db.parse(file).expect("imported file was already parsed"),
+                )
+            })
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            emit(args, &db, err);
+            return Err(ABORT_MESSAGE.into());
+        }
+    };
+
+    for (file, _) in &files {
+        for warning in db.analyze(*file) {
+            emit(args, &db, warning.into());
+        }
+    }
+
+    if args.emit_ast {
+        let rodeo = db.rodeo();
+        for item in items.iter() {
+            println!("{}", item.pretty_to_string(&rodeo, PRETTY_WIDTH));
+        }
+    }
+
+    let mut failed = false;
+    for (file, items) in &files {
+        let imported = files
+            .iter()
+            .filter(|(other, _)| other != file)
+            .flat_map(|(_, items)| items.iter().cloned())
+            .collect::<Vec<_>>();
+        for err in resolve_imported_names(&db.rodeo(), *file, items, &imported) {
+            emit(args, &db, err.into());
+            failed = true;
+        }
+    }
+    if failed {
+        return Err(ABORT_MESSAGE.into());
+    }
+
+    let ctx = Context::create();
+    let builder = ctx.create_builder();
+    let module = ctx.create_module(&db.name(file));
+
+    let fpm = create_pass_manager(&module, args.opt_level);
+
+    let mut compiler = Compiler::new(file, &ctx, &builder, &fpm, &module, db.rodeo());
+    if let Err(err) = compiler.compile_files(&files) {
+        emit(args, &db, err.into());
+        return Err(ABORT_MESSAGE.into());
+    }
+
+    if args.emit_ir {
+        print!("{}", module.print_to_string().to_string());
+    }
+
+    if args.run {
+        let result = compiler
+            .run_main(args.opt_level)
+            .map_err(|err| err.to_string())?;
+        println!("{}", result);
+        return Ok(());
+    }
+
+    if args.emit_bitcode {
+        return write_bitcode_file(&module, &args.output);
+    }
+
+    write_object_file(&module, args)
+}
+
+/// Emits the diagnostic using the error format specified in `args`.
+fn emit(args: &Args, db: &dyn SourceDatabase, diagnostic: Diagnostic) {
+    let result = match args.error_format {
+        ErrorFormat::Human => error::emit_with_color(db, diagnostic, args.color),
+        // JSON goes to stderr, so it isn't mixed with the output of `--emit-*` or `--run`.
+        ErrorFormat::Json => error::emit_json(&mut io::stderr(), db, diagnostic),
+    };
+    result.expect("failed to emit diagnostic");
+}
+
+/// Writes the given module as LLVM bitcode to `path`.
+fn write_bitcode_file(module: &Module<'_>, path: &Path) -> Result<(), String> {
+    if module.write_bitcode_to_path(path) {
+        Ok(())
+    } else {
+        Err(format!("failed to write '{}'", path.display()))
+    }
+}
+
+/// Writes the given module as an object file to the output specified in `args`.
+///
+/// If no target triple is given, the object file is compiled for the host.
+fn write_object_file(module: &Module<'_>, args: &Args) -> Result<(), String> {
+    let (path, target) = (&args.output, args.target.as_deref());
+    Target::initialize_all(&InitializationConfig::default());
+
+    let triple = match target {
+        Some(triple) => TargetTriple::create(triple),
+        None => TargetMachine::get_default_triple(),
+    };
+    let name = target.unwrap_or("host");
+
+    let target = Target::from_triple(&triple)
+        .map_err(|err| format!("unknown target '{}': {}", name, err))?;
+    let machine = target
+        .create_target_machine(
+            &triple,
+            "generic",
+            "",
+            args.opt_level,
+            RelocMode::Default,
+            CodeModel::Default,
+        )
+        .ok_or_else(|| format!("failed to create target machine for '{}'", name))?;
+    module.set_triple(&triple);
+
+    machine
+        .write_to_file(module, FileType::Object, path)
+        .map_err(|err| format!("failed to write '{}': {}", path.display(), err))
+}
@@ -4,7 +4,14 @@ use crate::{
     span::{Locatable, Span},
     Diagnostic, SourceDatabase,
 };
-use std::io;
+use codespan_reporting::diagnostic::LabelStyle;
+use codespan_reporting::term::{
+    self,
+    termcolor::{ColorChoice, StandardStream, WriteColor},
+};
+use serde_json::json;
+use smol_str::SmolStr;
+use std::{fmt, io, str::FromStr};
 
 /// A helper macro to generate `Diagnostic`s using a nice dsl.
 ///
@@ -58,12 +65,17 @@ pub trait IntoDiagnostic {
 /// Any error that can happen while code generation.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum CompileError {
-    UnknownVariable,
-    UnknownFunction,
+    UnknownVariable { suggestion: Option<SmolStr> },
+    UnknownFunction { suggestion: Option<SmolStr> },
     InvalidArguments { expected: usize, found: usize },
     UnknownOperator,
+    InvalidAssignment,
+    DivisionByZeroConstant,
+    NonConstantInitializer,
     InvalidCall,
     InvalidFunctionGenerated,
+    InvalidModule { message: String },
+    Redefinition { name: SmolStr },
 }
 
 pub type CompileResult<T> = std::result::Result<T, Locatable<CompileError>>;
@@ -71,18 +83,33 @@ pub type CompileResult<T> = std::result::Result<T, Locatable<CompileError>>;
 impl IntoDiagnostic for CompileError {
     fn into_diagnostic(self, file: FileId, span: Span) -> Diagnostic {
         match self {
-            CompileError::UnknownVariable => diagnostic! {
+            CompileError::UnknownVariable { suggestion } => diagnostic! {
                 error => "unknown variable",
                 label: primary("variable not in scope", file, span),
-            },
-            CompileError::UnknownFunction => diagnostic! {
+            }
+            .with_notes(did_you_mean(suggestion)),
+            CompileError::UnknownFunction { suggestion } => diagnostic! {
                 error => "unknown function",
                 label: primary("function not in scope", file, span),
-            },
+            }
+            .with_notes(did_you_mean(suggestion)),
             CompileError::UnknownOperator => diagnostic! {
                 error => "unknown operator",
                 label: primary("operator not in scope", file, span),
             },
+            CompileError::InvalidAssignment => diagnostic! {
+                error => "invalid assignment",
+                label: primary("can only assign to a variable", file, span),
+            },
+            CompileError::DivisionByZeroConstant => diagnostic! {
+                error => "division by zero",
+                label: primary("this divisor is always zero", file, span),
+            },
+            CompileError::NonConstantInitializer => diagnostic! {
+                error => "non-constant global initializer",
+                label: primary("this expression is not a constant", file, span),
+                note: "globals can only be initialized with numbers and builtin operators",
+            },
             CompileError::InvalidCall => diagnostic! {
                 error => "internal error",
                 label: primary("invalid call produced", file, span),
@@ -95,10 +122,32 @@ impl IntoDiagnostic for CompileError {
                 error => "invalid function generated",
                 label: primary("codegen generated invalid code for this function", file, span),
             },
+            CompileError::Redefinition { name } => diagnostic! {
+                error => format!("`{}` is defined multiple times", name),
+                label: primary(format!("`{}` redefined here", name), file, span),
+            }
+            .with_notes(if name == "main" {
+                vec!["every top level expression is compiled into a function named `main`".into()]
+            } else {
+                Vec::new()
+            }),
+            // The module has no location, so there's no label.
+            CompileError::InvalidModule { message } => diagnostic! {
+                error => "invalid module generated",
+                note: message,
+            },
         }
     }
 }
 
+/// Creates the note that suggests a similar name, if there is one.
+fn did_you_mean(suggestion: Option<SmolStr>) -> Vec<String> {
+    suggestion
+        .map(|name| format!("did you mean `{}`?", name))
+        .into_iter()
+        .collect()
+}
+
 /// Any error that can happen while parsing.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum SyntaxError {
@@ -107,10 +156,19 @@ pub enum SyntaxError {
     ExpectedOp { expected: char },
     ExpectedOneOf { expected: Vec<Kind>, found: Kind },
     UnexecptedEof,
+    UnexpectedToken { found: Kind },
+    UnterminatedComment,
     ExpectedExpression,
+    MissingBody,
+    MissingIn,
+    ZeroStep,
     InvalidNumber,
+    NumberOutOfRange,
     InvalidPrecedence,
     InvalidArgs(usize),
+    UnknownType { name: SmolStr },
+    MultiCharOperator { op: SmolStr },
+    OperatorRedefinition { op: SmolStr, builtin: bool },
 }
 
 pub type ParseResult<T> = std::result::Result<T, Locatable<SyntaxError>>;
@@ -120,38 +178,89 @@ impl IntoDiagnostic for SyntaxError {
         match self {
             SyntaxError::Expected { expected, found } => diagnostic! {
                 error => "unexpected token",
-                label: primary(format!("expected '{}', found '{}'", expected, found), file, span),
+                label: primary(format!("expected {}, found {}", expected, found), file, span),
             },
             SyntaxError::ExpectedOneOf { expected, found } => {
                 let expected = expected
                     .into_iter()
-                    .map(|kind| format!("'{}'", kind))
+                    .map(|kind| kind.to_string())
                     .collect::<Vec<_>>()
                     .join(", ");
                 diagnostic! {
                     error => "unexpected token",
-                    label: primary(format!("expected one of {}, found '{}'", expected, found), file, span),
+                    label: primary(format!("expected one of {}, found {}", expected, found), file, span),
                 }
             }
             SyntaxError::UnexecptedEof => diagnostic! {
-                error => "unexpected eof",
-                label: primary("unexpected eof here", file, span),
+                error => "unexpected end of input",
+                label: primary("expected more input here", file, span),
+            },
+            SyntaxError::UnexpectedToken { found } => diagnostic! {
+                error => "unexpected token",
+                label: primary(format!("unexpected {} after the expression", found), file, span),
+            },
+            SyntaxError::UnterminatedComment => diagnostic! {
+                error => "unterminated block comment",
+                label: primary("block comment starts here", file, span),
+                note: "block comments are closed with '*#' and can not be nested",
             },
             SyntaxError::ExpectedExpression => diagnostic! {
                 error => "expected expression",
                 label: primary("expected expression here", file, span),
             },
+            SyntaxError::MissingBody => diagnostic! {
+                error => "missing function body",
+                label: primary("this function has no body", file, span),
+                note: "use 'extern' to declare a function that is defined elsewhere",
+            },
+            SyntaxError::MissingIn => diagnostic! {
+                error => "missing 'in' after variable bindings",
+                label: primary("these bindings have no body", file, span),
+                note: "'var' bindings require 'in <body>', for example 'var x = 1 in x + 2'",
+                note: "to declare a global variable, use 'var x = 1;' outside of a function",
+            },
+            SyntaxError::ZeroStep => diagnostic! {
+                error => "zero step in for loop",
+                label: primary("the loop variable would never change", file, span),
+                note: "omit the step to increment the variable by 1",
+            },
             SyntaxError::InvalidNumber => diagnostic! {
                 error => "invalid number",
                 label: primary("is not a valid number", file, span),
             },
+            SyntaxError::NumberOutOfRange => diagnostic! {
+                error => "number literal is out of range",
+                label: primary("this number is too large", file, span),
+                note: format!("the largest allowed number is {:e}", f64::MAX),
+            },
             SyntaxError::InvalidPrecedence => diagnostic! {
                 error => "invalid precedence",
                 label: primary("the operator precedence must be 1..100", file, span),
             },
             SyntaxError::InvalidArgs(expected) => diagnostic! {
                 error => "invalid number of arguments",
-                label: primary(format!("expected function to have {} arguments", expected), file, span),
+                label: primary(match expected {
+                    1 => "expected operator to have 1 argument".to_string(),
+                    n => format!("expected operator to have {} arguments", n),
+                }, file, span),
+                note: "unary operators take 1 argument, binary operators take 2",
+            },
+            SyntaxError::UnknownType { name } => diagnostic! {
+                error => format!("unknown type `{}`", name),
+                label: primary("expected `double` or `void`", file, span),
+            },
+            SyntaxError::MultiCharOperator { op } => diagnostic! {
+                error => format!("invalid operator '{}'", op),
+                label: primary("user defined operators must be a single character", file, span),
+            },
+            SyntaxError::OperatorRedefinition { op, builtin: true } => diagnostic! {
+                error => format!("redefinition of builtin operator '{}'", op),
+                label: primary("builtin operators can not be redefined", file, span),
+            },
+            SyntaxError::OperatorRedefinition { op, builtin: false } => diagnostic! {
+                error => format!("redefinition of operator '{}'", op),
+                label: primary("this operator is already defined", file, span),
+                note: "every operator can only be defined once",
             },
             SyntaxError::ExpectedOp { expected } => diagnostic! {
                 error => "unexpected operator",
@@ -161,17 +270,297 @@ impl IntoDiagnostic for SyntaxError {
     }
 }
 
+/// Any warning that can be found while analyzing the AST.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SemanticWarning {
+    UnusedVariable {
+        name: SmolStr,
+    },
+    /// The end condition of a `for` loop is false for the start value,
+    /// so the body runs only once.
+    LoopRunsOnce,
+    /// The end condition of a `for` loop never becomes false.
+    InfiniteLoop,
+}
+
+impl IntoDiagnostic for SemanticWarning {
+    fn into_diagnostic(self, file: FileId, span: Span) -> Diagnostic {
+        match self {
+            SemanticWarning::UnusedVariable { name } => diagnostic! {
+                warning => format!("unused variable '{}'", name),
+                label: primary("this variable is never used", file, span),
+            },
+            SemanticWarning::LoopRunsOnce => diagnostic! {
+                warning => "for loop body runs only once",
+                label: primary("this condition is false for the start value", file, span),
+                note: "the condition is checked after every iteration, including the first",
+            },
+            SemanticWarning::InfiniteLoop => diagnostic! {
+                warning => "for loop never terminates",
+                label: primary("this condition is always true", file, span),
+                note: "the step moves the variable away from the bound, or the bound is infinite",
+            },
+        }
+    }
+}
+
+/// Any error that can happen while loading imported files.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ImportError {
+    /// The imported file could not be read.
+    Io { path: SmolStr, message: String },
+    /// The imported file imports the importing file, directly or through other files.
+    Cycle { path: SmolStr },
+    /// An imported file contains a top level expression.
+    TopLevelExpression,
+}
+
+impl IntoDiagnostic for ImportError {
+    fn into_diagnostic(self, file: FileId, span: Span) -> Diagnostic {
+        match self {
+            ImportError::Io { path, message } => diagnostic! {
+                error => format!("failed to import '{}'", path),
+                label: primary(message, file, span),
+            },
+            ImportError::Cycle { path } => diagnostic! {
+                error => format!("import cycle through '{}'", path),
+                label: primary("this file is already being imported", file, span),
+                note: "files can not import each other",
+            },
+            ImportError::TopLevelExpression => diagnostic! {
+                error => "top level expression in imported file",
+                label: primary("imported files may only contain definitions", file, span),
+            },
+        }
+    }
+}
+
+/// Any error that can happen while running the compiled `main` function.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RunError {
+    /// There is no `main` function, so there's nothing to run.
+    NoMain,
+    /// The JIT execution engine could not be created.
+    Jit(String),
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::NoMain => write!(f, "no `main` function to run"),
+            RunError::Jit(err) => write!(f, "failed to create the JIT: {}", err),
+        }
+    }
+}
+
+/// A missing `main` is only reported as a note, because it's not an error
+/// to compile definitions without running anything.
+impl From<RunError> for Diagnostic {
+    fn from(err: RunError) -> Self {
+        let diagnostic = match err {
+            RunError::NoMain => Diagnostic::note(),
+            RunError::Jit(_) => Diagnostic::error(),
+        };
+        diagnostic.with_message(err.to_string())
+    }
+}
+
 impl<T: IntoDiagnostic> Into<Diagnostic> for Locatable<T> {
     fn into(self) -> Diagnostic {
         let (data, span, file) = self.destruct();
         data.into_diagnostic(file, span)
     }
 }
+
+/// Emits the diagnostic to stdout.
 pub fn emit(db: &dyn SourceDatabase, err: Diagnostic) -> io::Result<()> {
-    use codespan_reporting::term::{self, termcolor};
+    emit_with_color(db, err, ColorChoice::Auto)
+}
 
+/// Emits the diagnostic to stdout, using the given color mode.
+pub fn emit_with_color(
+    db: &dyn SourceDatabase,
+    err: Diagnostic,
+    color: ColorChoice,
+) -> io::Result<()> {
+    let mut stdout = StandardStream::stdout(color);
+    emit_to(&mut stdout, db, err)
+}
+
+/// Converts `auto`, `always` or `never` into a `ColorChoice`.
+pub fn parse_color_choice(color: &str) -> Result<ColorChoice, String> {
+    match color.trim() {
+        "auto" => Ok(ColorChoice::Auto),
+        "always" => Ok(ColorChoice::Always),
+        "never" => Ok(ColorChoice::Never),
+        color => Err(format!(
+            "invalid color mode '{}', expected 'auto', 'always' or 'never'",
+            color
+        )),
+    }
+}
+
+/// Emits the diagnostic into the given writer.
+pub fn emit_to<W: WriteColor>(
+    writer: &mut W,
+    db: &dyn SourceDatabase,
+    err: Diagnostic,
+) -> io::Result<()> {
     let file_cache = FileCache::new(db);
-    let mut stdout = termcolor::StandardStream::stdout(termcolor::ColorChoice::Auto);
     let config = term::Config::default();
-    term::emit(&mut stdout, &config, &file_cache, &err.into())
+    term::emit(writer, &config, &file_cache, &err)
+}
+
+/// Emits the diagnostic as a single line of JSON into the given writer.
+///
+/// This is meant to be consumed by editors and other tools instead of humans.
+pub fn emit_json<W: io::Write>(
+    writer: &mut W,
+    db: &dyn SourceDatabase,
+    err: Diagnostic,
+) -> io::Result<()> {
+    let labels = err
+        .labels
+        .iter()
+        .map(|label| {
+            let style = match label.style {
+                LabelStyle::Primary => "primary",
+                LabelStyle::Secondary => "secondary",
+            };
+            json!({
+                "style": style,
+                "file": db.name(label.file_id).as_str(),
+                "start": label.range.start,
+                "end": label.range.end,
+                "message": label.message,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let json = json!({
+        "severity": format!("{:?}", err.severity).to_lowercase(),
+        "code": err.code,
+        "message": err.message,
+        "labels": labels,
+        "notes": err.notes,
+    });
+    writeln!(writer, "{}", json)
+}
+
+/// The format that is used to emit diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// The human readable format of `codespan_reporting`.
+    Human,
+    /// One JSON object per diagnostic.
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format!(
+                "invalid error format '{}', expected 'human' or 'json'",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{source::File, CompilerDatabase};
+    use codespan_reporting::term::termcolor::NoColor;
+    use std::sync::Arc;
+
+    #[test]
+    fn emit_syntax_error() {
+        let db = CompilerDatabase::default();
+        let file = File::new(Arc::new("test.kl".into()), Arc::new("def foo(x) ;".into()));
+        let file = db.intern_file(file);
+
+        let err = Span::new(11, 12).locate(file, SyntaxError::ExpectedExpression);
+        let mut writer = NoColor::new(Vec::new());
+        emit_to(&mut writer, &db, err.into()).unwrap();
+
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+        assert!(output.contains("error: expected expression"));
+        assert!(output.contains("test.kl:1:12"));
+        assert!(output.contains("expected expression here"));
+    }
+
+    #[test]
+    fn invalid_operator_args_note() {
+        let rodeo = Arc::new(Default::default());
+        let err = crate::parse::parse_program("def binary | (a) a;", rodeo).unwrap_err();
+        assert_eq!(err.data(), &SyntaxError::InvalidArgs(2));
+
+        let diagnostic: Diagnostic = err.into();
+        assert_eq!(diagnostic.labels[0].range, 14..15);
+        assert_eq!(
+            diagnostic.labels[0].message,
+            "expected operator to have 2 arguments"
+        );
+        assert_eq!(
+            diagnostic.notes,
+            ["unary operators take 1 argument, binary operators take 2"]
+        );
+    }
+
+    #[test]
+    fn emit_error_after_non_ascii() {
+        let db = CompilerDatabase::default();
+        let source = "# café\ndef fü(x) é;";
+        let file = File::new(Arc::new("test.kl".into()), Arc::new(source.into()));
+        let file = db.intern_file(file);
+
+        let start = source.rfind('é').unwrap();
+        let err =
+            Span::new(start, start + 'é'.len_utf8()).locate(file, SyntaxError::ExpectedExpression);
+        let mut writer = NoColor::new(Vec::new());
+        emit_to(&mut writer, &db, err.into()).unwrap();
+
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+        assert!(output.contains("test.kl:2:11"), "{}", output);
+
+        // The caret has to be below the character, not below its byte offset.
+        let line = output
+            .lines()
+            .find(|l| l.ends_with("def fü(x) é;"))
+            .unwrap();
+        let caret = output.lines().find(|l| l.contains('^')).unwrap();
+        let column = |s: &str, c: char| s.chars().position(|x| x == c).unwrap();
+        assert_eq!(column(line, 'é'), column(caret, '^'), "{}", output);
+    }
+
+    #[test]
+    fn emit_syntax_error_json() {
+        let db = CompilerDatabase::default();
+        let file = File::new(Arc::new("test.kl".into()), Arc::new("def foo(x) ;".into()));
+        let file = db.intern_file(file);
+
+        let err = Span::new(11, 12).locate(file, SyntaxError::ExpectedExpression);
+        let mut output = Vec::new();
+        emit_json(&mut output, &db, err.into()).unwrap();
+
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(json["severity"], "error");
+        assert_eq!(json["message"], "expected expression");
+        assert_eq!(json["labels"][0]["file"], "test.kl");
+        assert_eq!(json["labels"][0]["start"], 11);
+        assert_eq!(json["labels"][0]["end"], 12);
+    }
+
+    #[test]
+    fn color_choice() {
+        assert_eq!(parse_color_choice("never"), Ok(ColorChoice::Never));
+        assert_eq!(parse_color_choice("always"), Ok(ColorChoice::Always));
+        assert_eq!(parse_color_choice("auto"), Ok(ColorChoice::Auto));
+        assert!(parse_color_choice("sometimes").is_err());
+    }
 }
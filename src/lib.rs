@@ -1,32 +1,154 @@
 #![warn(rust_2018_idioms)]
 #![warn(clippy::all)]
 
+pub mod analysis;
 pub mod codegen;
 pub mod error;
 pub mod parse;
 pub mod pretty;
 pub mod source;
 pub mod span;
+#[cfg(test)]
+#[path = "../tests/common/mod.rs"]
+mod test_util;
 
+pub use codegen::{CodegenDatabase, CodegenDatabaseStorage};
+use error::{ImportError, ParseResult};
+use parse::ast::{Expr, Item, ItemKind};
 pub use parse::{FrontendDatabase, FrontendDatabaseStorage};
-use source::FileId;
+use source::{File, FileId};
 pub use source::{SourceDatabase, SourceDatabaseStorage};
-use std::io::Write;
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
 pub type Diagnostic = codespan_reporting::diagnostic::Diagnostic<FileId>;
 pub type Label = codespan_reporting::diagnostic::Label<FileId>;
 
-#[salsa::database(SourceDatabaseStorage, FrontendDatabaseStorage)]
+#[salsa::database(SourceDatabaseStorage, FrontendDatabaseStorage, CodegenDatabaseStorage)]
 #[derive(Default)]
 pub struct CompilerDatabase {
     storage: salsa::Storage<Self>,
+    /// All files that were added using `add_file`, in the order they were added.
+    files: Mutex<Vec<FileId>>,
 }
 
 impl salsa::Database for CompilerDatabase {}
 
-impl CompilerDatabase {}
+impl CompilerDatabase {
+    /// Interns the given file and remembers it, so it's returned by `all_files`.
+    ///
+    /// Adding the same file twice returns the same `FileId` and only lists it once.
+    pub fn add_file(&self, file: File) -> FileId {
+        let file = self.intern_file(file);
+        let mut files = self.files.lock().unwrap();
+        if !files.contains(&file) {
+            files.push(file);
+        }
+        file
+    }
 
-// TODO: Add helper methods (parse, parse_expr), that will take a `&str` argument.
+    /// Returns all files that were added using `add_file`, in the order they were added.
+    ///
+    /// Files that were interned using `intern_file` directly are not included.
+    pub fn all_files(&self) -> Vec<FileId> {
+        self.files.lock().unwrap().clone()
+    }
+
+    /// Interns the given source code as an anonymous file.
+    fn intern_source(&self, source: &str) -> FileId {
+        let file = File::new(Arc::new("<anon>".into()), Arc::new(source.into()));
+        self.add_file(file)
+    }
+
+    /// Reads the file at the given path and interns it, using the path as its name.
+    pub fn load_file(&self, path: &Path) -> io::Result<FileId> {
+        let source = std::fs::read_to_string(path)?;
+        let name = path.display().to_string();
+        let file = File::new(Arc::new(name.into()), Arc::new(source));
+        Ok(self.add_file(file))
+    }
+
+    /// Loads all files that are imported by `file`, directly or through other files.
+    ///
+    /// Every file comes after all files it imports, and the last file is `file` itself.
+    /// Imported files are identified by their canonical path, so files that are imported
+    /// multiple times are only returned once.
+    pub fn load_imports(&self, file: FileId) -> Result<Vec<FileId>, Diagnostic> {
+        let mut files = Vec::new();
+        self.visit_imports(file, &mut Vec::new(), &mut files)?;
+        Ok(files)
+    }
+
+    fn visit_imports(
+        &self,
+        file: FileId,
+        stack: &mut Vec<FileId>,
+        files: &mut Vec<FileId>,
+    ) -> Result<(), Diagnostic> {
+        if files.contains(&file) {
+            return Ok(());
+        }
+        stack.push(file);
+
+        let items = self.parse(file).map_err(Into::<Diagnostic>::into)?;
+        // Top level expressions of imported files would become a second `main` function.
+        if stack.len() > 1 {
+            if let Some(expr) = items.iter().find(|item| item.is_top_level_expr()) {
+                return Err(expr
+                    .span
+                    .locate(file, ImportError::TopLevelExpression)
+                    .into());
+            }
+        }
+
+        let name = self.name(file);
+        let dir = Path::new(name.as_str())
+            .parent()
+            .map_or_else(PathBuf::new, Path::to_path_buf);
+
+        for item in items.iter() {
+            let path = match &item.kind {
+                ItemKind::Import { path } => path,
+                _ => continue,
+            };
+            let located = |err| Into::<Diagnostic>::into(item.span.locate(file, err));
+
+            let import = std::fs::canonicalize(dir.join(path.as_str()))
+                .and_then(|path| self.load_file(&path))
+                .map_err(|err| {
+                    located(ImportError::Io {
+                        path: path.clone(),
+                        message: err.to_string(),
+                    })
+                })?;
+            if stack.contains(&import) {
+                return Err(located(ImportError::Cycle { path: path.clone() }));
+            }
+            self.visit_imports(import, stack, files)?;
+        }
+
+        stack.pop();
+        files.push(file);
+        Ok(())
+    }
+
+    /// Parses the given source code into a list of items.
+    pub fn parse_str(&self, source: &str) -> ParseResult<Vec<Item>> {
+        let file = self.intern_source(source);
+        self.parse(file)
+    }
+
+    /// Parses the given source code as a single expression.
+    ///
+    /// Fails if there's anything left after the expression.
+    pub fn parse_expr_str(&self, source: &str) -> ParseResult<Expr> {
+        let file = self.intern_source(source);
+        self.parse_expr(file)
+    }
+}
 
 macro_rules! print_flush {
     ( $( $x:expr ),* ) => {
@@ -46,3 +168,186 @@ pub extern "C" fn printd(x: f64) -> f64 {
     println!("{}", x);
     x
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse::{
+        ast::{ExprKind, ItemKind},
+        token::Kind,
+    };
+    use pretty::Pretty;
+    use span::Span;
+    use test_util::TempPath;
+
+    fn database() -> CompilerDatabase {
+        let mut db = CompilerDatabase::default();
+        db.set_rodeo(Arc::new(Default::default()));
+        db.set_predefined_operators(Arc::new(Vec::new()));
+        db
+    }
+
+    #[test]
+    fn parse_str() {
+        let db = database();
+        let items = db.parse_str("def foo(x) x; extern bar();").unwrap();
+        assert_eq!(items.len(), 2);
+        assert!(matches!(items[0].kind, ItemKind::Function { .. }));
+        assert!(matches!(items[1].kind, ItemKind::Extern { .. }));
+    }
+
+    #[test]
+    fn all_files() {
+        let db = database();
+        assert!(db.all_files().is_empty());
+
+        let a = db.intern_source("def a() 1;");
+        let b = db.add_file(File::new(
+            Arc::new("b.k".into()),
+            Arc::new("def b() 2;".into()),
+        ));
+        let c = db.intern_source("def c() 3;");
+        assert_eq!(db.intern_source("def a() 1;"), a);
+        assert_eq!(db.all_files(), [a, b, c]);
+
+        db.intern_file(File::new(Arc::new("d.k".into()), Arc::new("".into())));
+        assert_eq!(db.all_files().len(), 3);
+    }
+
+    #[test]
+    fn load_file() {
+        let path = TempPath::new("load_file.k");
+        std::fs::write(&*path, "def foo(x) x;").unwrap();
+
+        let db = database();
+        let file = db.load_file(&path).unwrap();
+        assert_eq!(db.source(file).as_str(), "def foo(x) x;");
+        assert_eq!(db.name(file).as_str(), path.display().to_string());
+
+        let missing = TempPath::new("missing_file.k");
+        assert!(db.load_file(&missing).is_err());
+    }
+
+    #[test]
+    fn load_imports() {
+        let dir = TempPath::new("imports");
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib/math.k"), "def square(x) x * x;").unwrap();
+        std::fs::write(
+            dir.join("lib/util.k"),
+            "import \"math.k\"; def quad(x) square(square(x));",
+        )
+        .unwrap();
+        let main = dir.join("main.k");
+        std::fs::write(
+            &main,
+            "import \"lib/math.k\"; import \"lib/util.k\"; quad(2)",
+        )
+        .unwrap();
+
+        let db = database();
+        let main = db.load_file(&main).unwrap();
+        let files = db.load_imports(main).unwrap();
+        let names = files
+            .iter()
+            .map(|file| {
+                Path::new(db.name(*file).as_str())
+                    .file_name()
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["math.k", "util.k", "main.k"]);
+    }
+
+    #[test]
+    fn import_cycle() {
+        let dir = TempPath::new("import_cycle");
+        std::fs::create_dir_all(&*dir).unwrap();
+        std::fs::write(dir.join("a.k"), "import \"b.k\"; def a() 1;").unwrap();
+        std::fs::write(dir.join("b.k"), "import \"a.k\"; def b() 2;").unwrap();
+
+        let db = database();
+        let file = db.load_file(&dir.join("a.k")).unwrap();
+        let err = db.load_imports(file).unwrap_err();
+        assert!(err.message.contains("import cycle"), "{}", err.message);
+
+        let file = db.intern_source("import \"missing.k\";");
+        let err = db.load_imports(file).unwrap_err();
+        assert!(err.message.contains("failed to import"), "{}", err.message);
+    }
+
+    #[test]
+    fn import_top_level_expression() {
+        let path = TempPath::new("import_expr.k");
+        std::fs::write(&*path, "def a() 1; a()").unwrap();
+
+        let db = database();
+        let file = db.intern_source(&format!("import \"{}\"; 2", path.display()));
+        let err = db.load_imports(file).unwrap_err();
+        assert_eq!(err.message, "top level expression in imported file");
+        assert_eq!(err.labels[0].range, 11..14);
+
+        // Top level expressions are fine in the importing file itself.
+        std::fs::write(&*path, "def a() 1;").unwrap();
+        let file = db.load_file(&path).unwrap();
+        assert!(db.load_imports(file).is_ok());
+    }
+
+    #[test]
+    fn tokens_query() {
+        let db = database();
+        let file = db.intern_source("def f(x) x + 1; # done");
+        let tokens = db.tokens(file);
+        let kinds = tokens.iter().map(|token| token.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                Kind::Def,
+                Kind::Identifier,
+                Kind::LeftParen,
+                Kind::Identifier,
+                Kind::RightParen,
+                Kind::Identifier,
+                Kind::Operator,
+                Kind::Number,
+                Kind::Semicolon,
+                Kind::Comment,
+            ]
+        );
+        assert_eq!(tokens[1].slice, "f");
+        assert_eq!(tokens[9].span, Span::new(16, 22));
+        assert!(Arc::ptr_eq(&tokens, &db.tokens(file)));
+    }
+
+    #[test]
+    fn parse_expr_query() {
+        let db = database();
+        let file = db.intern_source("1 + 2 * 3");
+        let expr = db.parse_expr(file).unwrap();
+        let rodeo = db.rodeo();
+        assert_eq!(expr.pretty_to_string(&rodeo, 80), "1 + 2 * 3");
+
+        let (left, right) = match &expr.kind {
+            ExprKind::Binary {
+                left, op, right, ..
+            } if op == "+" => (left, right),
+            kind => panic!("expected addition, found {:?}", kind),
+        };
+        assert!(matches!(left.kind, ExprKind::Number(_)));
+        assert!(matches!(&right.kind, ExprKind::Binary { op, .. } if op == "*"));
+
+        let file = db.intern_source("1 2");
+        assert!(db.parse_expr(file).is_err());
+    }
+
+    #[test]
+    fn parse_expr_str() {
+        let db = database();
+        let expr = db.parse_expr_str("foo(1) + 2").unwrap();
+        assert!(matches!(expr.kind, ExprKind::Binary { .. }));
+
+        let err = db.parse_expr_str("foo(1) + 2)").unwrap_err();
+        assert_eq!(err.span(), Span::new(10, 11));
+    }
+}
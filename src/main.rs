@@ -2,8 +2,18 @@
 #![warn(missing_debug_implementations)]
 #![warn(clippy::all)]
 
+mod driver;
 mod repl;
+#[cfg(test)]
+#[path = "../tests/common/mod.rs"]
+mod test_util;
 
+use codespan_reporting::term::termcolor::ColorChoice;
+use inkwell::OptimizationLevel;
+use kaleidoscope::{
+    codegen::{parse_opt_level, Compiler},
+    error::{parse_color_choice, ErrorFormat},
+};
 use std::{ffi::OsStr, io::Write, path::PathBuf};
 
 const HELP_MESSAGE: &str = concat!(
@@ -16,34 +26,56 @@ const HELP_MESSAGE: &str = concat!(
 
 FLAGS:
     -h, --help
+    -V, --version            Prints the version of the compiler and of LLVM.
         --emit-ast           If set, the compiler will print the AST. This flag will not affect the REPL.
         --emit-lex           If set, the compiler will print the tokens. This flag will not affect the REPL.
         --emit-ir            If set, the compiler will print generated LLVM IR. This flag will not affect the REPL.
+        --emit-bitcode       If set, LLVM bitcode is written to the output file instead of an object file.
+        --run                If set, the `main` function is executed and its result printed,
+                             instead of writing an object file. Takes precedence over --emit-bitcode.
 
 OPTIONS:
     -o, --output             The output file to use. (default: a.out)
+        --target             The target triple to compile for. (default: host)
+        --opt-level          The optimization level to use, from 0 to 3. (default: 0)
+        --error-format       The format of emitted diagnostics, either 'human' or 'json'. (default: human)
+                             JSON diagnostics are written to stderr, one per line.
+        --color              When to use colors in diagnostics, either 'auto', 'always' or 'never'. (default: auto)
 
 ARGS:
         <file>               The input file for the compiler. If no file is specified,
-                             the REPL will be started."
+                             the program is read from stdin, or the REPL will be
+                             started if stdin is a terminal."
 );
 
 /// The arguments for the CLI. Parsed by [`pico-args`].
 ///
 /// [`pico-args`]: https://docs.rs/pico-args
 #[derive(Debug)]
-struct Args {
+pub struct Args {
     /// Pretty prints the parsed AST.
     emit_ast: bool,
     /// Emits the LLVM IR.
     emit_ir: bool,
+    /// Writes LLVM bitcode instead of an object file.
+    emit_bitcode: bool,
     /// Emits the lex output.
     emit_lex: bool,
+    /// Runs the `main` function using the JIT instead of writing an object file.
+    run: bool,
     /// If provided, the file will be compiled.
     /// If no file is provided, the REPL will be started.
     file: Option<PathBuf>,
     /// Place the compiled output in this file.
     output: PathBuf,
+    /// The target triple to compile for. Uses the host if not provided.
+    target: Option<String>,
+    /// The optimization level used for codegen.
+    opt_level: OptimizationLevel,
+    /// The format that is used to emit diagnostics.
+    error_format: ErrorFormat,
+    /// Whether human readable diagnostics are colored.
+    color: ColorChoice,
 }
 
 fn main() {
@@ -55,8 +87,16 @@ fn main() {
         }
     };
 
-    if let Some(_) = args.file {
-        todo!()
+    if let Some(path) = &args.file {
+        if let Err(err) = driver::compile_file(&args, path) {
+            print_error(&args, &err);
+            std::process::exit(1);
+        }
+    } else if !atty::is(atty::Stream::Stdin) {
+        if let Err(err) = driver::compile_stdin(&args) {
+            print_error(&args, &err);
+            std::process::exit(1);
+        }
     } else {
         let mut repl = repl::Repl::new();
         match repl.run() {
@@ -69,6 +109,26 @@ fn main() {
     }
 }
 
+/// Prints the error that made the compilation fail.
+///
+/// JSON diagnostics already say why the compilation was aborted,
+/// so the human readable abort message would only break their consumers.
+fn print_error(args: &Args, err: &str) {
+    if args.error_format == ErrorFormat::Json && err == driver::ABORT_MESSAGE {
+        return;
+    }
+    println!("error: {}", err);
+}
+
+/// Returns the version of the compiler together with the LLVM version it uses.
+fn version() -> String {
+    format!(
+        "Kaleidoscope {} (LLVM {})",
+        env!("CARGO_PKG_VERSION"),
+        Compiler::llvm_version()
+    )
+}
+
 fn os_str_to_path_buf(os_str: &OsStr) -> Result<PathBuf, bool> {
     Ok(os_str.into())
 }
@@ -79,17 +139,43 @@ fn parse_args() -> Result<Args, pico_args::Error> {
         println!("{}", HELP_MESSAGE);
         std::process::exit(0);
     }
+    if args.contains(["-V", "--version"]) {
+        println!("{}", version());
+        std::process::exit(0);
+    }
 
     let output = args
         .opt_value_from_os_str(["-o", "--output"], os_str_to_path_buf)?
         .unwrap_or_else(|| "a.out".into());
+    let target = args.opt_value_from_str("--target")?;
+    let opt_level = args
+        .opt_value_from_fn("--opt-level", parse_opt_level)?
+        .unwrap_or(OptimizationLevel::None);
+    let error_format = args
+        .opt_value_from_str("--error-format")?
+        .unwrap_or(ErrorFormat::Human);
+    let color = args
+        .opt_value_from_fn("--color", parse_color_choice)?
+        .unwrap_or(ColorChoice::Auto);
+    let emit_ast = args.contains("--emit-ast");
+    let emit_ir = args.contains("--emit-ir");
+    let emit_bitcode = args.contains("--emit-bitcode");
+    let emit_lex = args.contains("--emit-lex");
+    let run = args.contains("--run");
+    // The free argument has to be parsed last, otherwise it would pick up any flag.
     let file = args.free_from_os_str(os_str_to_path_buf)?;
 
     Ok(Args {
-        emit_ast: args.contains("--emit-ast"),
-        emit_ir: args.contains("--emit-ir"),
-        emit_lex: args.contains("--emit-lex"),
+        emit_ast,
+        emit_ir,
+        emit_bitcode,
+        emit_lex,
+        run,
         file,
         output,
+        target,
+        opt_level,
+        error_format,
+        color,
     })
 }
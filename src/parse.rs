@@ -1,86 +1,293 @@
 use self::{
-    ast::{Expr, ExprKind, Identifier, Item, ItemKind, LetVar},
-    token::{Kind, Token, TokenStream},
+    ast::{Associativity, Expr, ExprKind, Identifier, Item, ItemKind, LetVar, NodeId, ReturnType},
+    token::{Kind, OwnedToken, Token, TokenStream},
 };
 use crate::{
-    error::{ParseResult, SyntaxError},
+    analysis,
+    error::{ParseResult, SemanticWarning, SyntaxError},
     source::{FileId, SourceDatabase},
     span::{Locatable, Span},
 };
 use lasso::ThreadedRodeo;
 use ordered_float::NotNan;
-use std::{collections::HashMap, iter::Peekable, sync::Arc};
+use smol_str::SmolStr;
+use std::{
+    collections::{HashMap, HashSet},
+    iter::Peekable,
+    sync::Arc,
+};
 
 pub mod ast;
 pub mod token;
 
+/// The precedence of all builtin binary operators.
+pub const BUILTIN_OPERATORS: &[(&str, i32)] = &[
+    ("=", 2),
+    ("||", 5),
+    ("&&", 6),
+    ("<", 10),
+    (">", 10),
+    ("<=", 10),
+    (">=", 10),
+    ("==", 10),
+    ("!=", 10),
+    ("+", 20),
+    ("-", 20),
+    ("*", 40),
+    ("/", 40),
+];
+
+/// All builtin unary operators.
+///
+/// `!x` is `1` if `x` is `0`, and `0` otherwise.
+pub const BUILTIN_UNARY_OPERATORS: &[char] = &['!'];
+
 #[salsa::query_group(FrontendDatabaseStorage)]
 pub trait FrontendDatabase: SourceDatabase {
     #[salsa::input]
     fn rodeo(&self) -> Arc<ThreadedRodeo>;
 
+    /// The operator definitions that are known in every file,
+    /// e.g. the ones from earlier input of a REPL session.
+    #[salsa::input]
+    fn predefined_operators(&self) -> Arc<Vec<Item>>;
+
+    /// Lexes the source code of the given file, including all comments.
+    fn tokens(&self, file: FileId) -> Arc<Vec<OwnedToken>>;
+
     /// Tries to parse the source code of the given file.
     fn parse(&self, file: FileId) -> ParseResult<Vec<Item>>;
+
+    /// Tries to parse the whole source code of the given file as a single expression.
+    fn parse_expr(&self, file: FileId) -> ParseResult<Expr>;
+
+    /// Analyzes the parsed items of the given file and returns all warnings.
+    ///
+    /// If the file can't be parsed, no warnings are returned.
+    fn analyze(&self, file: FileId) -> Vec<Locatable<SemanticWarning>>;
+}
+
+/// Parses a whole program without a database, e.g. for quick one-off parses.
+///
+/// All spans belong to the default `FileId`.
+pub fn parse_program(src: &str, rodeo: Arc<ThreadedRodeo>) -> ParseResult<Vec<Item>> {
+    Parser::new(rodeo, src, FileId::default()).parse()
+}
+
+fn tokens(db: &dyn FrontendDatabase, file: FileId) -> Arc<Vec<OwnedToken>> {
+    let code = db.source(file);
+    let tokens = TokenStream::with_comments(&code).map(Into::into).collect();
+    Arc::new(tokens)
 }
 
 fn parse(db: &dyn FrontendDatabase, file: FileId) -> ParseResult<Vec<Item>> {
     let code = db.source(file);
     let mut parser = Parser::new(db.rodeo(), &code, file);
+    parser.define_operators(&db.predefined_operators());
     parser.parse()
 }
 
+fn parse_expr(db: &dyn FrontendDatabase, file: FileId) -> ParseResult<Expr> {
+    let code = db.source(file);
+    let mut parser = Parser::new(db.rodeo(), &code, file);
+    parser.define_operators(&db.predefined_operators());
+    parser.parse_single_expr()
+}
+
+fn analyze(db: &dyn FrontendDatabase, file: FileId) -> Vec<Locatable<SemanticWarning>> {
+    match db.parse(file) {
+        Ok(items) => {
+            let mut warnings = analysis::unused_variables(&db.rodeo(), file, &items);
+            warnings.extend(analysis::loop_bounds(file, &items));
+            warnings
+        }
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Turns the comments in front of an item into its documentation.
+///
+/// The `#` of line comments, including one following space, and the
+/// delimiters of block comments are removed. Every comment is put on its own line.
+fn doc_comment(comments: &[Token<'_>]) -> Option<SmolStr> {
+    if comments.is_empty() {
+        return None;
+    }
+
+    let lines = comments
+        .iter()
+        .map(|comment| match comment.slice.strip_prefix("#*") {
+            Some(block) => block.strip_suffix("*#").unwrap_or(block).trim(),
+            None => {
+                let line = comment.slice[1..].trim_end();
+                line.strip_prefix(' ').unwrap_or(line)
+            }
+        })
+        .collect::<Vec<_>>();
+    Some(lines.join("\n").into())
+}
+
 #[derive(Clone)]
 #[allow(missing_debug_implementations)]
 pub struct Parser<'input> {
+    code: &'input str,
     tokens: Peekable<TokenStream<'input>>,
     rodeo: Arc<ThreadedRodeo>,
     file: FileId,
     eof_span: Span,
-    operators: HashMap<char, i32>,
+    operators: HashMap<SmolStr, i32>,
+    /// All binary operators that are right associative.
+    right_operators: HashSet<SmolStr>,
+    /// All unary operators that were defined so far.
+    unary_operators: HashSet<char>,
+    /// The operators from `define_operators` that weren't redefined yet,
+    /// together with whether they are binary.
+    predefined: HashSet<(char, bool)>,
+    /// The id that is given to the next node.
+    next_id: u32,
+    /// The comments in front of the next token.
+    comments: Vec<Token<'input>>,
+    /// The end of the last token that was consumed.
+    last_end: Option<usize>,
 }
 
 impl<'input> Parser<'input> {
     pub fn new(rodeo: Arc<ThreadedRodeo>, code: &'input str, file: FileId) -> Self {
-        let mut operators = HashMap::new();
-
-        operators.insert('=', 2);
-        operators.insert('<', 10);
-        operators.insert('+', 20);
-        operators.insert('-', 20);
-        operators.insert('*', 40);
-        operators.insert('/', 40);
+        let operators = BUILTIN_OPERATORS
+            .iter()
+            .map(|(op, prec)| ((*op).into(), *prec))
+            .collect();
+        Self::with_operators(rodeo, code, file, operators)
+    }
 
+    /// Creates a parser that only knows the given binary operators and their
+    /// precedence, instead of the `BUILTIN_OPERATORS`.
+    ///
+    /// The builtin operators still can't be redefined, because codegen
+    /// always lowers them to their builtin instructions.
+    pub fn with_operators(
+        rodeo: Arc<ThreadedRodeo>,
+        code: &'input str,
+        file: FileId,
+        operators: HashMap<SmolStr, i32>,
+    ) -> Self {
         Self {
+            code,
             rodeo,
-            tokens: TokenStream::new(&code).peekable(),
+            tokens: TokenStream::with_comments(&code).peekable(),
             file,
             eof_span: Span::new(code.len(), code.len()),
             operators,
+            right_operators: HashSet::new(),
+            unary_operators: BUILTIN_UNARY_OPERATORS.iter().copied().collect(),
+            predefined: HashSet::new(),
+            next_id: 0,
+            comments: Vec::new(),
+            last_end: None,
+        }
+    }
+
+    /// Registers the operators that are defined by the given items, e.g. in
+    /// earlier REPL input. Unlike operators of the parsed code, each of them
+    /// may be redefined once.
+    pub fn define_operators(&mut self, items: &[Item]) {
+        for item in items {
+            if let ItemKind::Operator {
+                op,
+                prec,
+                is_binary,
+                assoc,
+                ..
+            } = item.kind
+            {
+                self.register_operator(op, prec, is_binary, assoc);
+                self.predefined.insert((op, is_binary));
+            }
+        }
+    }
+
+    /// Registers a user defined operator and returns `true` if it was already defined.
+    fn register_operator(
+        &mut self,
+        op: char,
+        prec: isize,
+        binary: bool,
+        assoc: Associativity,
+    ) -> bool {
+        if !binary {
+            return !self.unary_operators.insert(op);
+        }
+
+        let name = SmolStr::from(op.to_string());
+        if assoc == Associativity::Right {
+            self.right_operators.insert(name.clone());
+        } else {
+            self.right_operators.remove(&name);
         }
+        self.operators.insert(name, prec as i32).is_some()
     }
 
     pub fn parse(&mut self) -> ParseResult<Vec<Item>> {
         let mut items = Vec::new();
-        while self.peek().is_ok() {
+        while self.has_tokens() {
             items.push(self.parse_item()?)
         }
         Ok(items)
     }
 
+    /// Checks if there are any tokens left, ignoring comments.
+    fn has_tokens(&mut self) -> bool {
+        self.skip_comments();
+        self.tokens.peek().is_some()
+    }
+
+    /// Skips all comments in front of the next token and remembers them,
+    /// so they can be attached to the next item.
+    ///
+    /// A comment on the same line as the previous token belongs to that token.
+    fn skip_comments(&mut self) {
+        while self
+            .tokens
+            .peek()
+            .map_or(false, |token| token.kind == Kind::Comment)
+        {
+            let comment = self.tokens.next().unwrap();
+            let trailing = match self.last_end {
+                Some(end) if self.comments.is_empty() => {
+                    !self.code[end..comment.span.start()].contains('\n')
+                }
+                _ => false,
+            };
+            if !trailing {
+                self.comments.push(comment);
+            }
+        }
+    }
+
     fn peek(&mut self) -> ParseResult<&Token<'input>> {
-        self.tokens.peek().ok_or(Locatable::new(
-            SyntaxError::UnexecptedEof,
-            self.eof_span,
-            self.file,
-        ))
+        self.skip_comments();
+        let file = self.file;
+        match self.tokens.peek() {
+            Some(token) if token.is_unterminated_comment() => Err(Locatable::new(
+                SyntaxError::UnterminatedComment,
+                token.span,
+                file,
+            )),
+            Some(token) => Ok(token),
+            None => Err(Locatable::new(
+                SyntaxError::UnexecptedEof,
+                self.eof_span,
+                file,
+            )),
+        }
     }
 
     fn next(&mut self) -> ParseResult<Token<'input>> {
-        self.tokens.next().ok_or(Locatable::new(
-            SyntaxError::UnexecptedEof,
-            self.eof_span,
-            self.file,
-        ))
+        self.peek()?;
+        self.comments.clear();
+        let token = self.tokens.next().unwrap();
+        self.last_end = Some(token.span.end());
+        Ok(token)
     }
 
     fn next_is(&mut self, kind: Kind) -> bool {
@@ -136,26 +343,81 @@ impl<'input> Parser<'input> {
 // Top level parsing
 impl<'input> Parser<'input> {
     pub fn parse_item(&mut self) -> ParseResult<Item> {
-        let token = self.peek()?;
-        match token.kind {
+        let kind = self.peek()?.kind;
+        let doc = doc_comment(&self.comments);
+        let item = match kind {
             Kind::Def | Kind::Extern => self.parse_def(),
-            _ => {
-                let expr = self.parse_expr()?;
-                Ok(Item {
-                    span: expr.span,
-                    kind: ItemKind::Function {
-                        // The original tutorial names the top level expression function `__anon_expr`,
-                        // but I think "main" makes much more sense.
-                        name: Identifier {
-                            spur: self.rodeo.get_or_intern("main"),
-                            span: expr.span,
-                        },
-                        args: Vec::new(),
-                        body: Box::new(expr),
-                    },
+            Kind::Import => self.parse_import(),
+            Kind::Var => {
+                // A top level `var` is either a global, or a `var` / `in` expression.
+                let checkpoint = self.clone();
+                self.parse_global().or_else(|_| {
+                    *self = checkpoint;
+                    self.parse_top_level_expr()
                 })
             }
-        }
+            _ => self.parse_top_level_expr(),
+        }?;
+        Ok(Item { doc, ..item })
+    }
+
+    fn parse_global(&mut self) -> ParseResult<Item> {
+        let var_span = self.eat(Kind::Var)?.span;
+        let name = self.eat(Kind::Identifier)?;
+        let name = self.intern_identifier(&name);
+
+        let init = if self.next_is(Kind::Operator) {
+            self.eat_equal()?;
+            Some(Box::new(self.parse_expr()?))
+        } else {
+            None
+        };
+
+        let semi = self.eat(Kind::Semicolon)?.span;
+        Ok(Item {
+            id: self.node_id(),
+            doc: None,
+            span: var_span.merge(semi),
+            kind: ItemKind::Global { name, init },
+        })
+    }
+
+    fn parse_import(&mut self) -> ParseResult<Item> {
+        let import_span = self.eat(Kind::Import)?.span;
+        let path = self.eat(Kind::String)?.slice;
+        let semi = self.eat(Kind::Semicolon)?.span;
+        Ok(Item {
+            id: self.node_id(),
+            doc: None,
+            span: import_span.merge(semi),
+            kind: ItemKind::Import {
+                path: path[1..path.len() - 1].into(),
+            },
+        })
+    }
+
+    fn parse_top_level_expr(&mut self) -> ParseResult<Item> {
+        let expr = self.parse_expr()?;
+        // Like definitions, top level expressions may end with a semicolon.
+        let span = match self.eat(Kind::Semicolon) {
+            Ok(semi) => expr.span.merge(semi.span),
+            Err(_) => expr.span,
+        };
+        Ok(Item {
+            id: self.node_id(),
+            doc: None,
+            span,
+            kind: ItemKind::Function {
+                // The original tutorial names the top level expression function `__anon_expr`,
+                // but I think "main" makes much more sense.
+                name: Identifier {
+                    spur: self.rodeo.get_or_intern("main"),
+                    span: expr.span,
+                },
+                args: Vec::new(),
+                body: Box::new(expr),
+            },
+        })
     }
 
     fn parse_def(&mut self) -> ParseResult<Item> {
@@ -170,12 +432,34 @@ impl<'input> Parser<'input> {
                 while let Ok(name) = self.eat(Kind::Identifier) {
                     args.push(self.intern_identifier(&name));
                 }
+                let variadic = self.eat(Kind::Ellipsis).is_ok();
 
                 self.eat(Kind::RightParen)?;
+                let ret = if self.eat(Kind::Arrow).is_ok() {
+                    let ty = self.eat(Kind::Identifier)?;
+                    ReturnType::from_name(ty.slice).ok_or_else(|| {
+                        ty.span.locate(
+                            self.file,
+                            SyntaxError::UnknownType {
+                                name: ty.slice.into(),
+                            },
+                        )
+                    })?
+                } else {
+                    ReturnType::Double
+                };
+
                 let semi = self.eat(Kind::Semicolon)?.span;
                 Ok(Item {
+                    id: self.node_id(),
+                    doc: None,
                     span: def.span.merge(semi),
-                    kind: ItemKind::Extern { name, args },
+                    kind: ItemKind::Extern {
+                        name,
+                        args,
+                        variadic,
+                        ret,
+                    },
                 })
             }
             Kind::Def => {
@@ -192,14 +476,25 @@ impl<'input> Parser<'input> {
     }
 
     fn parse_operator(&mut self, def_span: Span, binary: bool) -> ParseResult<Item> {
-        let op = match self.eat(Kind::Operator)? {
-            Token {
-                kind: Kind::Operator,
-                slice,
-                ..
-            } => slice.chars().next().unwrap(),
-            _ => unreachable!(),
-        };
+        let mut op_span = self.eat(Kind::Operator)?.span;
+        // Operators like `<<` are lexed as one token per character,
+        // so all directly following operators are part of the name.
+        while self.peek().map_or(false, |token| {
+            token.kind == Kind::Operator && token.span.start() == op_span.end()
+        }) {
+            op_span = op_span.merge(self.next()?.span);
+        }
+
+        let name = op_span.index_in(self.code);
+        let mut chars = name.chars();
+        let op = chars.next().unwrap();
+        if chars.next().is_some() {
+            return Err(Locatable::new(
+                SyntaxError::MultiCharOperator { op: name.into() },
+                op_span,
+                self.file,
+            ));
+        }
 
         let prec = if binary {
             if self.next_is(Kind::Number) {
@@ -222,7 +517,37 @@ impl<'input> Parser<'input> {
         } else {
             -1
         };
-        self.operators.insert(op, prec as i32);
+
+        let assoc = match self.peek()?.slice {
+            "left" if binary => Some(Associativity::Left),
+            "right" if binary => Some(Associativity::Right),
+            _ => None,
+        };
+        if assoc.is_some() {
+            self.next()?;
+        }
+        let assoc = assoc.unwrap_or(Associativity::Left);
+
+        // Register the operator, so it can be used by all following items.
+        // Unary operators don't have a precedence and must not shadow
+        // a binary operator with the same symbol.
+        let name = SmolStr::from(op.to_string());
+        let builtin = if binary {
+            BUILTIN_OPERATORS
+                .iter()
+                .any(|(builtin, _)| *builtin == name)
+        } else {
+            BUILTIN_UNARY_OPERATORS.contains(&op)
+        };
+        let redefined = self.register_operator(op, prec, binary, assoc)
+            && !self.predefined.remove(&(op, binary));
+        if redefined || builtin {
+            return Err(Locatable::new(
+                SyntaxError::OperatorRedefinition { op: name, builtin },
+                op_span,
+                self.file,
+            ));
+        }
 
         let l_paren = self.eat(Kind::LeftParen)?.span;
 
@@ -238,19 +563,25 @@ impl<'input> Parser<'input> {
 
         let argc = if binary { 2 } else { 1 };
         if args.len() != argc {
+            // Point at the arguments if there are any, otherwise at the parenthesis.
+            let span = Span::merge_all(args.iter().map(|arg| arg.span))
+                .unwrap_or_else(|| l_paren.merge(r_paren));
             return Err(Locatable::new(
                 SyntaxError::InvalidArgs(argc),
-                l_paren.merge(r_paren),
+                span,
                 self.file,
             ));
         }
 
         Ok(Item {
+            id: self.node_id(),
+            doc: None,
             span: def_span.merge(semi),
             kind: ItemKind::Operator {
                 op,
                 prec,
                 is_binary: binary,
+                assoc,
                 body: Box::new(body),
                 args,
             },
@@ -266,11 +597,22 @@ impl<'input> Parser<'input> {
             args.push(self.intern_identifier(&name));
         }
 
-        self.eat(Kind::RightParen)?;
+        let r_paren = self.eat(Kind::RightParen)?.span;
+
+        // A function without a body is most likely meant to be an `extern`.
+        if self.next_is(Kind::Semicolon) {
+            return Err(Locatable::new(
+                SyntaxError::MissingBody,
+                def_span.merge(r_paren),
+                self.file,
+            ));
+        }
 
         let body = self.parse_expr()?;
         let semi = self.eat(Kind::Semicolon)?.span;
         Ok(Item {
+            id: self.node_id(),
+            doc: None,
             span: def_span.merge(semi),
             kind: ItemKind::Function {
                 name,
@@ -288,6 +630,20 @@ impl<'input> Parser<'input> {
         self.parse_bin_op(0, lhs)
     }
 
+    /// Parses a single expression, which must be followed by the end of the input.
+    pub fn parse_single_expr(&mut self) -> ParseResult<Expr> {
+        let expr = self.parse_expr()?;
+        self.skip_comments();
+        match self.tokens.peek() {
+            Some(token) => Err(Locatable::new(
+                SyntaxError::UnexpectedToken { found: token.kind },
+                token.span,
+                self.file,
+            )),
+            None => Ok(expr),
+        }
+    }
+
     fn token_precendence(&mut self) -> i32 {
         let token = if let Ok(Token {
             kind: Kind::Operator,
@@ -295,11 +651,11 @@ impl<'input> Parser<'input> {
             ..
         }) = self.peek()
         {
-            slice.chars().next().unwrap()
+            *slice
         } else {
             return -1;
         };
-        self.operators.get(&token).copied().unwrap_or(-1)
+        self.operators.get(token).copied().unwrap_or(-1)
     }
 
     fn parse_bin_op(&mut self, prec: i32, mut lhs: Expr) -> ParseResult<Expr> {
@@ -309,26 +665,36 @@ impl<'input> Parser<'input> {
                 return Ok(lhs);
             }
 
-            let bin_op = match self.eat(Kind::Operator)? {
+            let (bin_op, op_span): (SmolStr, _) = match self.eat(Kind::Operator)? {
                 Token {
                     kind: Kind::Operator,
                     slice,
-                    ..
-                } => slice.chars().next().unwrap(),
+                    span,
+                } => (slice.into(), span),
                 _ => unreachable!(),
             };
             let mut rhs = self.parse_unary()?;
 
+            // A right associative operator also takes the following operators
+            // with the same precedence into its right operand.
+            let right_assoc = self.right_operators.contains(&bin_op);
             let next_prec = self.token_precendence();
-            if token_prec < next_prec {
-                rhs = self.parse_bin_op(token_prec + 1, rhs)?;
+            if token_prec < next_prec || (right_assoc && token_prec == next_prec) {
+                let min_prec = if right_assoc {
+                    token_prec
+                } else {
+                    token_prec + 1
+                };
+                rhs = self.parse_bin_op(min_prec, rhs)?;
             }
 
             lhs = Expr {
+                id: self.node_id(),
                 span: lhs.span.merge(rhs.span),
                 kind: ExprKind::Binary {
                     left: Box::new(lhs),
                     op: bin_op,
+                    op_span,
                     right: Box::new(rhs),
                 },
             }
@@ -340,8 +706,27 @@ impl<'input> Parser<'input> {
             return self.parse_primary();
         }
         let op = self.eat(Kind::Operator)?;
+
+        // A minus directly in front of a number is a negative literal
+        // instead of a unary operator.
+        let number_start = match self.peek() {
+            Ok(token) if token.kind == Kind::Number => Some(token.span.start()),
+            _ => None,
+        };
+        if op.slice == "-" && number_start == Some(op.span.end()) {
+            let num = self.parse_primary()?;
+            if let ExprKind::Number(x) = num.kind {
+                return Ok(Expr {
+                    id: self.node_id(),
+                    span: op.span.merge(num.span),
+                    kind: ExprKind::Number(-x),
+                });
+            }
+        }
+
         let val = self.parse_unary()?;
         Ok(Expr {
+            id: self.node_id(),
             span: op.span.merge(val.span),
             kind: ExprKind::Unary {
                 op: op.slice.chars().next().unwrap(),
@@ -353,11 +738,14 @@ impl<'input> Parser<'input> {
     fn parse_primary(&mut self) -> ParseResult<Expr> {
         let token = self.peek()?;
         match token.kind {
+            // Parenthesis only affect the structure of the AST and are not kept,
+            // the pretty printer inserts them again where they are needed.
             Kind::LeftParen => {
                 let l_paren = self.next().unwrap().span;
                 let expr = self.parse_expr()?;
                 let r_paren = self.eat(Kind::RightParen)?.span;
                 Ok(Expr {
+                    id: expr.id,
                     span: l_paren.merge(r_paren),
                     kind: expr.kind,
                 })
@@ -368,11 +756,19 @@ impl<'input> Parser<'input> {
 
                 let number_err =
                     || Locatable::new(SyntaxError::InvalidNumber, token.span, self.file);
-                let num = num
-                    .parse::<f64>()
-                    .map_err(|_| number_err())
-                    .and_then(|num| NotNan::new(num).map_err(|_| number_err()))?;
+                let num = num.parse::<f64>().map_err(|_| number_err())?;
+                // Literals that are too large are parsed as infinity,
+                // which is not allowed in the source code.
+                if num.is_infinite() {
+                    return Err(Locatable::new(
+                        SyntaxError::NumberOutOfRange,
+                        token.span,
+                        self.file,
+                    ));
+                }
+                let num = NotNan::new(num).map_err(|_| number_err())?;
                 Ok(Expr {
+                    id: self.node_id(),
                     span: token.span,
                     kind: ExprKind::Number(num),
                 })
@@ -383,6 +779,7 @@ impl<'input> Parser<'input> {
 
                 if !self.next_is(Kind::LeftParen) {
                     return Ok(Expr {
+                        id: self.node_id(),
                         span: token.span,
                         kind: ExprKind::Var(identifier),
                     });
@@ -401,6 +798,7 @@ impl<'input> Parser<'input> {
                 }
                 let r_paren = self.eat(Kind::RightParen)?.span;
                 Ok(Expr {
+                    id: self.node_id(),
                     span: identifier.span.merge(r_paren),
                     kind: ExprKind::Call {
                         callee: identifier,
@@ -413,14 +811,20 @@ impl<'input> Parser<'input> {
                 let cond = self.parse_expr()?;
                 self.eat(Kind::Then)?;
                 let then = self.parse_expr()?;
-                self.eat(Kind::Else)?;
-                let else_ = self.parse_expr()?;
+                // A dangling `else` always belongs to the innermost `if`.
+                let else_ = if self.eat(Kind::Else).is_ok() {
+                    Some(self.parse_expr()?)
+                } else {
+                    None
+                };
+                let end = else_.as_ref().map_or(then.span, |else_| else_.span);
                 Ok(Expr {
-                    span: if_span.merge(else_.span),
+                    id: self.node_id(),
+                    span: if_span.merge(end),
                     kind: ExprKind::If {
                         cond: Box::new(cond),
                         then: Box::new(then),
-                        else_: Box::new(else_),
+                        else_: else_.map(Box::new),
                     },
                 })
             }
@@ -434,13 +838,25 @@ impl<'input> Parser<'input> {
                 let end = self.parse_expr()?;
 
                 let step = if let Ok(_) = self.eat(Kind::Comma) {
-                    Some(self.parse_expr()?)
+                    let step = self.parse_expr()?;
+                    // A literal zero step would never reach the end of the loop.
+                    if let ExprKind::Number(x) = step.kind {
+                        if x.into_inner() == 0.0 {
+                            return Err(Locatable::new(
+                                SyntaxError::ZeroStep,
+                                step.span,
+                                self.file,
+                            ));
+                        }
+                    }
+                    Some(step)
                 } else {
                     None
                 };
                 self.eat(Kind::In)?;
                 let body = self.parse_expr()?;
                 Ok(Expr {
+                    id: self.node_id(),
                     span: for_span.merge(body.span),
                     kind: ExprKind::For {
                         var: name,
@@ -451,6 +867,39 @@ impl<'input> Parser<'input> {
                     },
                 })
             }
+            Kind::While => {
+                let while_span = self.next().unwrap().span;
+                let cond = self.parse_expr()?;
+                self.eat(Kind::In)?;
+                let body = self.parse_expr()?;
+                Ok(Expr {
+                    id: self.node_id(),
+                    span: while_span.merge(body.span),
+                    kind: ExprKind::While {
+                        cond: Box::new(cond),
+                        body: Box::new(body),
+                    },
+                })
+            }
+            Kind::LeftBrace => {
+                let l_brace = self.next().unwrap().span;
+
+                let mut exprs = Vec::new();
+                while !self.next_is(Kind::RightBrace) {
+                    exprs.push(self.parse_expr()?);
+                    // The last expression may omit the semicolon.
+                    if self.eat(Kind::Semicolon).is_err() {
+                        break;
+                    }
+                }
+
+                let r_brace = self.eat(Kind::RightBrace)?.span;
+                Ok(Expr {
+                    id: self.node_id(),
+                    span: l_brace.merge(r_brace),
+                    kind: ExprKind::Block { exprs },
+                })
+            }
             Kind::Var => {
                 let var_span = self.next().unwrap().span;
 
@@ -459,16 +908,12 @@ impl<'input> Parser<'input> {
                     let name = self.eat(Kind::Identifier)?;
                     let name = self.intern_identifier(&name);
 
-                    let init = match self.peek()? {
-                        Token {
-                            kind: Kind::Operator,
-                            slice: "=",
-                            ..
-                        } => {
-                            self.next().unwrap();
-                            Some(self.parse_expr()?)
-                        }
-                        _ => None,
+                    let is_equal = |tok: &Token<'_>| tok.kind == Kind::Operator && tok.slice == "=";
+                    let init = if self.peek().map_or(false, is_equal) {
+                        self.next().unwrap();
+                        Some(self.parse_expr()?)
+                    } else {
+                        None
                     };
 
                     vars.push(LetVar { name, val: init });
@@ -479,9 +924,19 @@ impl<'input> Parser<'input> {
                     self.eat(Kind::Comma)?;
                 }
 
+                if !self.next_is(Kind::In) {
+                    let last = vars.last().unwrap();
+                    let end = last.val.as_ref().map_or(last.name.span, |val| val.span);
+                    return Err(Locatable::new(
+                        SyntaxError::MissingIn,
+                        var_span.merge(end),
+                        self.file,
+                    ));
+                }
                 self.eat(Kind::In)?;
                 let body = self.parse_expr()?;
                 Ok(Expr {
+                    id: self.node_id(),
                     span: var_span.merge(body.span),
                     kind: ExprKind::Let {
                         vars,
@@ -504,6 +959,13 @@ impl<'input> Parser<'input> {
             span: token.span,
         }
     }
+
+    /// Returns a new, unique id for a node.
+    fn node_id(&mut self) -> NodeId {
+        let id = NodeId(self.next_id);
+        self.next_id += 1;
+        id
+    }
 }
 
 #[cfg(test)]
@@ -518,8 +980,713 @@ mod tests {
         panic!();
     }
 
+    fn expr(code: &str) -> Expr {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let mut parser = Parser::new(rodeo, code, FileId::default());
+        parser.parse_expr().unwrap()
+    }
+
+    fn call_args(expr: &Expr) -> &[Expr] {
+        match &expr.kind {
+            ExprKind::Call { args, .. } => args,
+            kind => panic!("expected call, found {:?}", kind),
+        }
+    }
+
     #[test]
     fn parse_expr() {
         assert("1 + 1");
     }
+
+    #[test]
+    fn parse_node_ids() {
+        use crate::parse::ast::{walk_expr, walk_item, Visitor};
+
+        #[derive(Default)]
+        struct Ids(Vec<NodeId>);
+
+        impl Visitor for Ids {
+            fn visit_item(&mut self, item: &Item) {
+                self.0.push(item.id);
+                walk_item(self, item);
+            }
+
+            fn visit_expr(&mut self, expr: &Expr) {
+                self.0.push(expr.id);
+                walk_expr(self, expr);
+            }
+        }
+
+        let ids = |code: &str| {
+            let rodeo = Arc::new(ThreadedRodeo::new());
+            let items = Parser::new(rodeo, code, FileId::default()).parse().unwrap();
+            let mut ids = Ids::default();
+            items.iter().for_each(|item| ids.visit_item(item));
+            ids.0
+        };
+
+        let code = "def f(x) if x < 1 then (x) else f(x - 1); var y = 2; f(y) + 1";
+        let first = ids(code);
+        assert_eq!(first.len(), 17);
+        let unique = first.iter().collect::<HashSet<_>>();
+        assert_eq!(unique.len(), first.len());
+        assert_eq!(first, ids(code));
+    }
+
+    #[test]
+    fn parse_call_without_args() {
+        let expr = expr("foo()");
+        assert_eq!(expr.span, Span::new(0, 5));
+        assert!(call_args(&expr).is_empty());
+    }
+
+    #[test]
+    fn parse_call_with_arg() {
+        let expr = expr("foo(1)");
+        assert_eq!(expr.span, Span::new(0, 6));
+        assert_eq!(call_args(&expr).len(), 1);
+    }
+
+    #[test]
+    fn parse_call_with_args() {
+        let code = "foo(1, 2+3, bar(4))";
+        let expr = expr(code);
+        assert_eq!(expr.span, Span::new(0, code.len()));
+
+        let args = call_args(&expr);
+        assert_eq!(args.len(), 3);
+        assert!(matches!(args[0].kind, ExprKind::Number(_)));
+        assert!(matches!(args[1].kind, ExprKind::Binary { .. }));
+        assert_eq!(call_args(&args[2]).len(), 1);
+    }
+
+    #[test]
+    fn parse_program_without_database() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let items = parse_program("def f(x) x + 1; extern g(y);", Arc::clone(&rodeo)).unwrap();
+
+        assert_eq!(items.len(), 2);
+        assert!(matches!(items[0].kind, ItemKind::Function { .. }));
+        assert!(matches!(items[1].kind, ItemKind::Extern { .. }));
+        assert!(rodeo.contains("g"));
+
+        let err = parse_program("def f(x", rodeo).unwrap_err();
+        assert_eq!(err.file(), FileId::default());
+    }
+
+    #[test]
+    fn parse_top_level_extern() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let mut parser = Parser::new(Arc::clone(&rodeo), "extern sin(x);", FileId::default());
+        let items = parser.parse().unwrap();
+
+        assert_eq!(items.len(), 1);
+        match &items[0].kind {
+            ItemKind::Extern {
+                name,
+                args,
+                variadic,
+                ret,
+            } => {
+                assert_eq!(rodeo.resolve(&name.spur), "sin");
+                assert_eq!(args.len(), 1);
+                assert!(!variadic);
+                assert_eq!(*ret, ReturnType::Double);
+            }
+            kind => panic!("expected extern, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn parse_typed_extern() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let items = parse_program(
+            "extern putchard(x) -> void; extern sin(x) -> double;",
+            rodeo,
+        )
+        .unwrap();
+        assert!(matches!(
+            items[0].kind,
+            ItemKind::Extern {
+                ret: ReturnType::Void,
+                ..
+            }
+        ));
+        assert!(matches!(
+            items[1].kind,
+            ItemKind::Extern {
+                ret: ReturnType::Double,
+                ..
+            }
+        ));
+
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let err = parse_program("extern f(x) -> float;", rodeo).unwrap_err();
+        assert_eq!(
+            err.data(),
+            &SyntaxError::UnknownType {
+                name: "float".into()
+            }
+        );
+        assert_eq!(err.span(), Span::new(15, 20));
+    }
+
+    #[test]
+    fn parse_variadic_extern() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let mut parser = Parser::new(rodeo, "extern printd(x ...);", FileId::default());
+        let items = parser.parse().unwrap();
+
+        match &items[0].kind {
+            ItemKind::Extern { args, variadic, .. } => {
+                assert_eq!(args.len(), 1);
+                assert!(variadic);
+            }
+            kind => panic!("expected extern, found {:?}", kind),
+        }
+
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let mut parser = Parser::new(rodeo, "extern f(... x);", FileId::default());
+        let err = parser.parse().unwrap_err();
+        assert_eq!(
+            err.data(),
+            &SyntaxError::Expected {
+                expected: Kind::RightParen,
+                found: Kind::Identifier,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_while() {
+        let expr = expr("while x < 3 in foo(x)");
+        assert_eq!(expr.span, Span::new(0, 21));
+        match expr.kind {
+            ExprKind::While { cond, body } => {
+                assert!(matches!(cond.kind, ExprKind::Binary { .. }));
+                assert!(matches!(body.kind, ExprKind::Call { .. }));
+            }
+            kind => panic!("expected while, found {:?}", kind),
+        }
+    }
+
+    fn function_body(item: &Item) -> &Expr {
+        match &item.kind {
+            ItemKind::Function { body, .. } => body,
+            kind => panic!("expected function, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn parse_user_defined_binary_operator() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let code = "def binary : 1 (a b) a; def f(a b) a : b;";
+        let items = Parser::new(rodeo, code, FileId::default()).parse().unwrap();
+
+        match &function_body(&items[1]).kind {
+            ExprKind::Binary { op, .. } => assert_eq!(op, ":"),
+            kind => panic!("expected binary, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn unary_operator_keeps_binary_precedence() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let code = "def unary - (v) 0 - v; def f(a b) a - b * 2;";
+        let items = Parser::new(rodeo, code, FileId::default()).parse().unwrap();
+
+        match &function_body(&items[1]).kind {
+            ExprKind::Binary { op, right, .. } => {
+                assert_eq!(op, "-");
+                assert!(matches!(right.kind, ExprKind::Binary { .. }));
+            }
+            kind => panic!("expected binary, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn parse_with_custom_operators() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let operators = std::iter::once(("|".into(), 5)).collect();
+        let code = "def f(a b c) a | b | c;";
+        let items = Parser::with_operators(rodeo, code, FileId::default(), operators)
+            .parse()
+            .unwrap();
+
+        match &function_body(&items[0]).kind {
+            ExprKind::Binary { left, op, .. } => {
+                assert_eq!(op, "|");
+                assert!(matches!(left.kind, ExprKind::Binary { .. }));
+            }
+            kind => panic!("expected binary, found {:?}", kind),
+        }
+
+        // Builtin operators are unknown, but still can't be redefined.
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let code = "def f(a b) a + b;";
+        let err = Parser::with_operators(rodeo, code, FileId::default(), HashMap::new())
+            .parse()
+            .unwrap_err();
+        assert_eq!(
+            err.data(),
+            &SyntaxError::Expected {
+                expected: Kind::Semicolon,
+                found: Kind::Operator
+            }
+        );
+
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let code = "def binary + 20 (a b) a; def f(a b) a + b;";
+        let err = Parser::with_operators(rodeo, code, FileId::default(), HashMap::new())
+            .parse()
+            .unwrap_err();
+        assert_eq!(
+            err.data(),
+            &SyntaxError::OperatorRedefinition {
+                op: "+".into(),
+                builtin: true
+            }
+        );
+    }
+
+    #[test]
+    fn parse_predefined_operators() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let defs = Parser::new(
+            Arc::clone(&rodeo),
+            "def binary | 5 (a b) a;",
+            FileId::default(),
+        )
+        .parse()
+        .unwrap();
+
+        let mut parser = Parser::new(Arc::clone(&rodeo), "def f(a b) a | b;", FileId::default());
+        parser.define_operators(&defs);
+        let items = parser.parse().unwrap();
+        assert!(matches!(
+            function_body(&items[0]).kind,
+            ExprKind::Binary { .. }
+        ));
+
+        // A predefined operator may only be redefined once.
+        let code = "def binary | 5 (a b) b; def binary | 5 (a b) a;";
+        let mut parser = Parser::new(rodeo, code, FileId::default());
+        parser.define_operators(&defs);
+        let err = parser.parse().unwrap_err();
+        assert!(matches!(
+            err.data(),
+            SyntaxError::OperatorRedefinition { builtin: false, .. }
+        ));
+    }
+
+    #[test]
+    fn parse_not() {
+        match expr("!a && b").kind {
+            ExprKind::Binary { left, op, .. } => {
+                assert_eq!(op, "&&");
+                assert!(matches!(left.kind, ExprKind::Unary { op: '!', .. }));
+            }
+            kind => panic!("expected binary, found {:?}", kind),
+        }
+        assert!(matches!(
+            expr("!(a || b)").kind,
+            ExprKind::Unary { op: '!', .. }
+        ));
+    }
+
+    #[test]
+    fn parse_doc_comments() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let code = "# Adds two numbers.\n#\n#   a + b\ndef add(a b) a + b; # not a doc\n\
+                    def f() 1;\n#* A block\n comment *# extern sin(x);\n\
+                    var g; # trailing";
+        let items = parse_program(code, rodeo).unwrap();
+
+        let docs = items
+            .iter()
+            .map(|item| item.doc.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            docs,
+            [
+                Some("Adds two numbers.\n\n  a + b"),
+                None,
+                Some("A block\n comment"),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_top_level_expression() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let mut parser = Parser::new(Arc::clone(&rodeo), "4+5;", FileId::default());
+        let items = parser.parse().unwrap();
+
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].span, Span::new(0, 4));
+        match &items[0].kind {
+            ItemKind::Function { name, args, body } => {
+                assert_eq!(rodeo.resolve(&name.spur), "main");
+                assert!(args.is_empty());
+                assert_eq!(body.span, Span::new(0, 3));
+            }
+            kind => panic!("expected function, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn parse_unterminated_comment() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let mut parser = Parser::new(rodeo, "def f() 1; #* oops", FileId::default());
+        let err = parser.parse().unwrap_err();
+
+        assert_eq!(err.data(), &SyntaxError::UnterminatedComment);
+        assert_eq!(err.span(), Span::new(11, 18));
+    }
+
+    #[test]
+    fn parse_function_without_body() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let mut parser = Parser::new(rodeo, "def f(x);", FileId::default());
+        let err = parser.parse().unwrap_err();
+
+        assert_eq!(err.data(), &SyntaxError::MissingBody);
+        assert_eq!(err.span(), Span::new(0, 8));
+    }
+
+    fn number(expr: &Expr) -> f64 {
+        match expr.kind {
+            ExprKind::Number(x) => x.into_inner(),
+            ref kind => panic!("expected number, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn parse_negative_number() {
+        let expr = expr("-5");
+        assert_eq!(expr.span, Span::new(0, 2));
+        assert_eq!(number(&expr), -5.0);
+    }
+
+    #[test]
+    fn parse_subtract_negative_number() {
+        match expr("3 - -5").kind {
+            ExprKind::Binary {
+                left, op, right, ..
+            } => {
+                assert_eq!(number(&left), 3.0);
+                assert_eq!(op, "-");
+                assert_eq!(number(&right), -5.0);
+                assert_eq!(right.span, Span::new(4, 6));
+            }
+            kind => panic!("expected binary, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn parse_negative_number_precedence() {
+        match expr("-2 * 3").kind {
+            ExprKind::Binary { left, op, .. } => {
+                assert_eq!(number(&left), -2.0);
+                assert_eq!(op, "*");
+            }
+            kind => panic!("expected binary, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn parse_negated_variable() {
+        match expr("-x").kind {
+            ExprKind::Unary { op, val } => {
+                assert_eq!(op, '-');
+                assert!(matches!(val.kind, ExprKind::Var(_)));
+            }
+            kind => panic!("expected unary, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn parse_operator_with_invalid_args() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let code = "def binary : 1 (a b c) a;";
+        let err = Parser::new(Arc::clone(&rodeo), code, FileId::default())
+            .parse()
+            .unwrap_err();
+        assert_eq!(err.data(), &SyntaxError::InvalidArgs(2));
+        assert_eq!(err.span(), Span::new(16, 21));
+
+        let code = "def unary ~ () 1;";
+        let err = Parser::new(rodeo, code, FileId::default())
+            .parse()
+            .unwrap_err();
+        assert_eq!(err.data(), &SyntaxError::InvalidArgs(1));
+        assert_eq!(err.span(), Span::new(12, 14));
+    }
+
+    #[test]
+    fn parse_if_without_else() {
+        let if_ = expr("if x then 1");
+        assert_eq!(if_.span, Span::new(0, 11));
+        assert!(matches!(if_.kind, ExprKind::If { else_: None, .. }));
+
+        match expr("if a then if b then 1 else 2").kind {
+            ExprKind::If { then, else_, .. } => {
+                assert!(else_.is_none());
+                assert!(matches!(then.kind, ExprKind::If { else_: Some(_), .. }));
+            }
+            kind => panic!("expected if, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn parse_for_with_zero_step() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let mut parser = Parser::new(rodeo, "for i = 0, i < 10, 0 in x", FileId::default());
+        let err = parser.parse_expr().unwrap_err();
+
+        assert_eq!(err.data(), &SyntaxError::ZeroStep);
+        assert_eq!(err.span(), Span::new(19, 20));
+    }
+
+    #[test]
+    fn parse_comment_between_tokens() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let code = "def #* the name *# foo(x) # the body\n x;";
+        let items = Parser::new(Arc::clone(&rodeo), code, FileId::default())
+            .parse()
+            .unwrap();
+
+        assert_eq!(items.len(), 1);
+        match &items[0].kind {
+            ItemKind::Function { name, .. } => assert_eq!(rodeo.resolve(&name.spur), "foo"),
+            kind => panic!("expected function, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn parse_block() {
+        let code = "{ x = 1; foo(x); x + 2 }";
+        let expr = expr(code);
+        assert_eq!(expr.span, Span::new(0, code.len()));
+        match expr.kind {
+            ExprKind::Block { exprs } => {
+                assert_eq!(exprs.len(), 3);
+                assert!(matches!(exprs[1].kind, ExprKind::Call { .. }));
+                assert!(matches!(exprs[2].kind, ExprKind::Binary { .. }));
+            }
+            kind => panic!("expected block, found {:?}", kind),
+        }
+
+        assert!(matches!(expr("{ 1; }").kind, ExprKind::Block { exprs } if exprs.len() == 1));
+        assert!(matches!(expr("{}").kind, ExprKind::Block { exprs } if exprs.is_empty()));
+    }
+
+    fn parse_error(code: &str) -> Locatable<SyntaxError> {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        Parser::new(rodeo, code, FileId::default())
+            .parse()
+            .unwrap_err()
+    }
+
+    #[test]
+    fn parse_number_out_of_range() {
+        let code = format!("def f() 1{};", "0".repeat(400));
+        let err = parse_error(&code);
+        assert_eq!(err.data(), &SyntaxError::NumberOutOfRange);
+        assert_eq!(err.span(), Span::new(8, 409));
+
+        let code = format!("def f() 1{};", "0".repeat(300));
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        assert!(Parser::new(rodeo, &code, FileId::default()).parse().is_ok());
+    }
+
+    #[test]
+    fn parse_unknown_binary_operator() {
+        let err = parse_error("def f(a b) a $ b;");
+        assert_eq!(
+            err.data(),
+            &SyntaxError::Expected {
+                expected: Kind::Semicolon,
+                found: Kind::Operator,
+            }
+        );
+        assert_eq!(err.span(), Span::new(13, 14));
+    }
+
+    #[test]
+    fn parse_trailing_tokens() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let err = Parser::new(rodeo, "1 + 2)", FileId::default())
+            .parse_single_expr()
+            .unwrap_err();
+        assert_eq!(
+            err.data(),
+            &SyntaxError::UnexpectedToken {
+                found: Kind::RightParen
+            }
+        );
+        assert_eq!(err.span(), Span::new(5, 6));
+
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let code = "1 + 2 # comment";
+        assert!(Parser::new(rodeo, code, FileId::default())
+            .parse_single_expr()
+            .is_ok());
+    }
+
+    #[test]
+    fn parse_var_without_in() {
+        let err = parse_error("def f() var x = 1, y;");
+        assert_eq!(err.data(), &SyntaxError::MissingIn);
+        assert_eq!(err.span(), Span::new(8, 20));
+
+        let err = parse_error("var x = 1 + 2");
+        assert_eq!(err.data(), &SyntaxError::MissingIn);
+        assert_eq!(err.span(), Span::new(0, 13));
+    }
+
+    #[test]
+    fn parse_operator_associativity() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let code = "def binary ^ 50 right (a b) a; def binary ~ 50 (a b) a; a ^ b ^ c; a ~ b ~ c";
+        let items = Parser::new(rodeo, code, FileId::default()).parse().unwrap();
+
+        assert!(matches!(
+            items[0].kind,
+            ItemKind::Operator {
+                assoc: Associativity::Right,
+                ..
+            }
+        ));
+        assert!(matches!(
+            items[1].kind,
+            ItemKind::Operator {
+                assoc: Associativity::Left,
+                ..
+            }
+        ));
+
+        let operands = |expr: &Expr| match &expr.kind {
+            ExprKind::Binary { left, right, .. } => (left.kind.clone(), right.kind.clone()),
+            kind => panic!("expected binary, found {:?}", kind),
+        };
+        let (left, right) = operands(function_body(&items[2]));
+        assert!(matches!(left, ExprKind::Var(_)));
+        assert!(matches!(right, ExprKind::Binary { .. }));
+
+        let (left, right) = operands(function_body(&items[3]));
+        assert!(matches!(left, ExprKind::Binary { .. }));
+        assert!(matches!(right, ExprKind::Var(_)));
+    }
+
+    #[test]
+    fn parse_multi_char_operator() {
+        let err = parse_error("def binary << 5 (a b) a;");
+        assert_eq!(
+            err.data(),
+            &SyntaxError::MultiCharOperator { op: "<<".into() }
+        );
+        assert_eq!(err.span(), Span::new(11, 13));
+
+        let err = parse_error("def binary && 5 (a b) a;");
+        assert_eq!(
+            err.data(),
+            &SyntaxError::MultiCharOperator { op: "&&".into() }
+        );
+
+        let err = parse_error("def unary !~ (v) v;");
+        assert_eq!(
+            err.data(),
+            &SyntaxError::MultiCharOperator { op: "!~".into() }
+        );
+        assert_eq!(err.span(), Span::new(10, 12));
+    }
+
+    #[test]
+    fn parse_operator_redefinition() {
+        let err = parse_error("def binary + 10 (a b) a;");
+        assert_eq!(
+            err.data(),
+            &SyntaxError::OperatorRedefinition {
+                op: "+".into(),
+                builtin: true
+            }
+        );
+        assert_eq!(err.span(), Span::new(11, 12));
+
+        let err = parse_error("def binary : 5 (a b) a; def binary : 5 (a b) b;");
+        assert_eq!(
+            err.data(),
+            &SyntaxError::OperatorRedefinition {
+                op: ":".into(),
+                builtin: false
+            }
+        );
+
+        let err = parse_error("def unary ~ (v) v; def unary ~ (v) 0;");
+        assert_eq!(err.span(), Span::new(29, 30));
+
+        let err = parse_error("def unary ! (v) v;");
+        assert_eq!(
+            err.data(),
+            &SyntaxError::OperatorRedefinition {
+                op: "!".into(),
+                builtin: true
+            }
+        );
+        assert_eq!(err.span(), Span::new(10, 11));
+    }
+
+    #[test]
+    fn parse_import() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let code = "import \"lib/math.k\"; foo()";
+        let items = Parser::new(rodeo, code, FileId::default()).parse().unwrap();
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].span, Span::new(0, 20));
+        assert_eq!(
+            items[0].kind,
+            ItemKind::Import {
+                path: "lib/math.k".into()
+            }
+        );
+
+        let err = parse_error("import foo;");
+        assert_eq!(
+            err.data(),
+            &SyntaxError::Expected {
+                expected: Kind::String,
+                found: Kind::Identifier,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_global() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let code = "var half = 0.5; var x; var y = 1 in y";
+        let items = Parser::new(Arc::clone(&rodeo), code, FileId::default())
+            .parse()
+            .unwrap();
+
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].span, Span::new(0, 15));
+        match &items[0].kind {
+            ItemKind::Global { name, init } => {
+                assert_eq!(rodeo.resolve(&name.spur), "half");
+                assert_eq!(number(init.as_ref().unwrap()), 0.5);
+            }
+            kind => panic!("expected global, found {:?}", kind),
+        }
+        assert!(matches!(
+            &items[1].kind,
+            ItemKind::Global { init: None, .. }
+        ));
+        assert!(matches!(
+            function_body(&items[2]).kind,
+            ExprKind::Let { .. }
+        ));
+    }
 }
@@ -1,21 +1,38 @@
 use crate::span::Span;
 use lasso::Spur;
 use ordered_float::NotNan;
+use smol_str::SmolStr;
+
+/// Identifies a single `Expr` or `Item` of a parsed file.
+///
+/// Ids are assigned in the order in which the parser finishes the nodes,
+/// so they are unique inside a file and stable if the same source is parsed again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeId(pub u32);
 
 /// An Identifier name is interned using `lasso`.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Identifier {
+    #[cfg_attr(feature = "serde", serde(with = "serde_impl::spur"))]
     pub spur: Spur,
     pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Item {
+    pub id: NodeId,
     pub span: Span,
     pub kind: ItemKind,
+    /// The text of the comments in front of the item.
+    #[cfg_attr(feature = "serde", serde(with = "serde_impl::opt_smol_str"))]
+    pub doc: Option<SmolStr>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ItemKind {
     Function {
         name: Identifier,
@@ -25,6 +42,9 @@ pub enum ItemKind {
     Extern {
         name: Identifier,
         args: Vec<Identifier>,
+        /// True if the extern accepts additional arguments after `args`.
+        variadic: bool,
+        ret: ReturnType,
     },
     Operator {
         op: char,
@@ -32,20 +52,76 @@ pub enum ItemKind {
         /// True if the operator is binary, false if its a unary op.
         /// The precedence is -1 if it's a unary op
         is_binary: bool,
+        /// Unary operators are always `Left` associative.
+        assoc: Associativity,
         body: Box<Expr>,
         args: Vec<Identifier>,
     },
+    /// A global variable, which is initialized to `0` if there's no initializer.
+    Global {
+        name: Identifier,
+        init: Option<Box<Expr>>,
+    },
+    /// Imports all definitions of another file.
+    ///
+    /// The path is relative to the directory of the importing file.
+    Import {
+        #[cfg_attr(feature = "serde", serde(with = "serde_impl::smol_str"))]
+        path: SmolStr,
+    },
+}
+
+/// The type that is returned by an extern function.
+///
+/// Kaleidoscope functions always return a `double`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ReturnType {
+    Double,
+    /// Calls to `void` functions evaluate to `0`.
+    Void,
+}
+
+impl ReturnType {
+    /// Returns the type with the given name, as it's written after the `->`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "double" => Some(ReturnType::Double),
+            "void" => Some(ReturnType::Void),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ReturnType::Double => "double",
+            ReturnType::Void => "void",
+        }
+    }
+}
+
+/// The associativity of a binary operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Associativity {
+    /// `a + b + c` is parsed as `(a + b) + c`.
+    Left,
+    /// `a ^ b ^ c` is parsed as `a ^ (b ^ c)`.
+    Right,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Expr {
+    pub id: NodeId,
     pub span: Span,
     pub kind: ExprKind,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ExprKind {
-    Number(NotNan<f64>),
+    Number(#[cfg_attr(feature = "serde", serde(with = "serde_impl::not_nan"))] NotNan<f64>),
     Var(Identifier),
     Unary {
         op: char,
@@ -53,7 +129,10 @@ pub enum ExprKind {
     },
     Binary {
         left: Box<Expr>,
-        op: char,
+        #[cfg_attr(feature = "serde", serde(with = "serde_impl::smol_str"))]
+        op: SmolStr,
+        /// The span of the operator itself.
+        op_span: Span,
         right: Box<Expr>,
     },
     Call {
@@ -63,7 +142,8 @@ pub enum ExprKind {
     If {
         cond: Box<Expr>,
         then: Box<Expr>,
-        else_: Box<Expr>,
+        /// The `else` branch is optional and evaluates to `0` if omitted.
+        else_: Option<Box<Expr>>,
     },
     For {
         var: Identifier,
@@ -72,6 +152,15 @@ pub enum ExprKind {
         step: Option<Box<Expr>>,
         body: Box<Expr>,
     },
+    While {
+        cond: Box<Expr>,
+        body: Box<Expr>,
+    },
+    /// A sequence of expressions separated by `;`, which
+    /// evaluates to the value of the last expression.
+    Block {
+        exprs: Vec<Expr>,
+    },
     /// The var / in expression
     Let {
         vars: Vec<LetVar>,
@@ -80,7 +169,461 @@ pub enum ExprKind {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LetVar {
     pub name: Identifier,
     pub val: Option<Expr>,
 }
+
+impl Identifier {
+    /// Checks if both identifiers have the same name, regardless of their location.
+    pub fn eq_ignore_span(&self, other: &Self) -> bool {
+        self.spur == other.spur
+    }
+}
+
+impl Item {
+    /// Checks if this item is a top level expression, which the parser wraps
+    /// into a `main` function that shares the span with its body.
+    pub fn is_top_level_expr(&self) -> bool {
+        match &self.kind {
+            ItemKind::Function { name, body, .. } => name.span == body.span,
+            _ => false,
+        }
+    }
+
+    /// Compares the structure and documentation of both items,
+    /// ignoring all spans and node ids.
+    pub fn eq_ignore_span(&self, other: &Self) -> bool {
+        use ItemKind::*;
+
+        if self.doc != other.doc {
+            return false;
+        }
+
+        match (&self.kind, &other.kind) {
+            (
+                Function { name, args, body },
+                Function {
+                    name: other_name,
+                    args: other_args,
+                    body: other_body,
+                },
+            ) => {
+                name.eq_ignore_span(other_name)
+                    && all_eq(args, other_args, Identifier::eq_ignore_span)
+                    && body.eq_ignore_span(other_body)
+            }
+            (
+                Extern {
+                    name,
+                    args,
+                    variadic,
+                    ret,
+                },
+                Extern {
+                    name: other_name,
+                    args: other_args,
+                    variadic: other_variadic,
+                    ret: other_ret,
+                },
+            ) => {
+                name.eq_ignore_span(other_name)
+                    && all_eq(args, other_args, Identifier::eq_ignore_span)
+                    && variadic == other_variadic
+                    && ret == other_ret
+            }
+            (
+                Operator {
+                    op,
+                    prec,
+                    is_binary,
+                    assoc,
+                    body,
+                    args,
+                },
+                Operator {
+                    op: other_op,
+                    prec: other_prec,
+                    is_binary: other_is_binary,
+                    assoc: other_assoc,
+                    body: other_body,
+                    args: other_args,
+                },
+            ) => {
+                (op, prec, is_binary, assoc) == (other_op, other_prec, other_is_binary, other_assoc)
+                    && all_eq(args, other_args, Identifier::eq_ignore_span)
+                    && body.eq_ignore_span(other_body)
+            }
+            (
+                Global { name, init },
+                Global {
+                    name: other_name,
+                    init: other_init,
+                },
+            ) => {
+                name.eq_ignore_span(other_name)
+                    && option_eq(init, other_init, |a, b| a.eq_ignore_span(b))
+            }
+            (Import { path }, Import { path: other_path }) => path == other_path,
+            _ => false,
+        }
+    }
+}
+
+impl Expr {
+    /// Compares the structure of both expressions, ignoring all spans and node ids.
+    pub fn eq_ignore_span(&self, other: &Self) -> bool {
+        use ExprKind::*;
+
+        match (&self.kind, &other.kind) {
+            (Number(x), Number(other_x)) => x == other_x,
+            (Var(name), Var(other_name)) => name.eq_ignore_span(other_name),
+            (
+                Unary { op, val },
+                Unary {
+                    op: other_op,
+                    val: other_val,
+                },
+            ) => op == other_op && val.eq_ignore_span(other_val),
+            (
+                Binary {
+                    left, op, right, ..
+                },
+                Binary {
+                    left: other_left,
+                    op: other_op,
+                    right: other_right,
+                    ..
+                },
+            ) => {
+                op == other_op
+                    && left.eq_ignore_span(other_left)
+                    && right.eq_ignore_span(other_right)
+            }
+            (
+                Call { callee, args },
+                Call {
+                    callee: other_callee,
+                    args: other_args,
+                },
+            ) => {
+                callee.eq_ignore_span(other_callee)
+                    && all_eq(args, other_args, Expr::eq_ignore_span)
+            }
+            (
+                If { cond, then, else_ },
+                If {
+                    cond: other_cond,
+                    then: other_then,
+                    else_: other_else,
+                },
+            ) => {
+                cond.eq_ignore_span(other_cond)
+                    && then.eq_ignore_span(other_then)
+                    && option_eq(else_, other_else, |a, b| a.eq_ignore_span(b))
+            }
+            (
+                For {
+                    var,
+                    start,
+                    end,
+                    step,
+                    body,
+                },
+                For {
+                    var: other_var,
+                    start: other_start,
+                    end: other_end,
+                    step: other_step,
+                    body: other_body,
+                },
+            ) => {
+                var.eq_ignore_span(other_var)
+                    && start.eq_ignore_span(other_start)
+                    && end.eq_ignore_span(other_end)
+                    && option_eq(step, other_step, |a, b| a.eq_ignore_span(b))
+                    && body.eq_ignore_span(other_body)
+            }
+            (
+                While { cond, body },
+                While {
+                    cond: other_cond,
+                    body: other_body,
+                },
+            ) => cond.eq_ignore_span(other_cond) && body.eq_ignore_span(other_body),
+            (Block { exprs }, Block { exprs: other_exprs }) => {
+                all_eq(exprs, other_exprs, Expr::eq_ignore_span)
+            }
+            (
+                Let { vars, body },
+                Let {
+                    vars: other_vars,
+                    body: other_body,
+                },
+            ) => {
+                let var_eq = |a: &LetVar, b: &LetVar| {
+                    a.name.eq_ignore_span(&b.name)
+                        && option_eq(&a.val, &b.val, Expr::eq_ignore_span)
+                };
+                all_eq(vars, other_vars, var_eq) && body.eq_ignore_span(other_body)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Checks if both slices have the same length and all elements are equal according to `eq`.
+fn all_eq<T>(a: &[T], b: &[T], eq: impl Fn(&T, &T) -> bool) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| eq(a, b))
+}
+
+/// Checks if both options are `None`, or both are `Some` and equal according to `eq`.
+fn option_eq<T>(a: &Option<T>, b: &Option<T>, eq: impl Fn(&T, &T) -> bool) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Serde implementations for the foreign types inside the AST.
+#[cfg(feature = "serde")]
+mod serde_impl {
+    /// Serializes a `Spur` as its raw key, so it can only be resolved
+    /// using the same rodeo again.
+    pub mod spur {
+        use lasso::{Key, Spur};
+        use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(spur: &Spur, s: S) -> Result<S::Ok, S::Error> {
+            s.serialize_u64(spur.into_usize() as u64)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Spur, D::Error> {
+            let key = u64::deserialize(d)?;
+            Spur::try_from_usize(key as usize).ok_or_else(|| D::Error::custom("invalid spur key"))
+        }
+    }
+
+    /// Serializes a `NotNan` as a plain float and rejects `NaN` while deserializing.
+    pub mod not_nan {
+        use ordered_float::NotNan;
+        use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(num: &NotNan<f64>, s: S) -> Result<S::Ok, S::Error> {
+            s.serialize_f64(num.into_inner())
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<NotNan<f64>, D::Error> {
+            let num = f64::deserialize(d)?;
+            NotNan::new(num).map_err(|_| D::Error::custom("number is NaN"))
+        }
+    }
+
+    /// Serializes a `SmolStr` as a plain string, because `smol_str`
+    /// is used without its `serde` feature.
+    pub mod smol_str {
+        use serde::{Deserialize, Deserializer, Serializer};
+        use smol_str::SmolStr;
+
+        pub fn serialize<S: Serializer>(s: &SmolStr, ser: S) -> Result<S::Ok, S::Error> {
+            ser.serialize_str(s)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<SmolStr, D::Error> {
+            String::deserialize(d).map(SmolStr::from)
+        }
+    }
+
+    /// Like `smol_str`, but for an optional string.
+    pub mod opt_smol_str {
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+        use smol_str::SmolStr;
+
+        pub fn serialize<S: Serializer>(s: &Option<SmolStr>, ser: S) -> Result<S::Ok, S::Error> {
+            s.as_deref().serialize(ser)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<SmolStr>, D::Error> {
+            Option::<String>::deserialize(d).map(|s| s.map(SmolStr::from))
+        }
+    }
+}
+
+/// A visitor that walks through the AST.
+///
+/// Every method walks into the children of the node by default,
+/// so an implementation only has to override the nodes it cares about.
+pub trait Visitor: Sized {
+    fn visit_item(&mut self, item: &Item) {
+        walk_item(self, item)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+}
+
+/// Visits all children of the given item.
+pub fn walk_item<V: Visitor>(visitor: &mut V, item: &Item) {
+    match &item.kind {
+        ItemKind::Function { body, .. } | ItemKind::Operator { body, .. } => {
+            visitor.visit_expr(body)
+        }
+        ItemKind::Global { init, .. } => {
+            if let Some(init) = init {
+                visitor.visit_expr(init)
+            }
+        }
+        ItemKind::Extern { .. } | ItemKind::Import { .. } => {}
+    }
+}
+
+/// Visits all children of the given expression.
+pub fn walk_expr<V: Visitor>(visitor: &mut V, expr: &Expr) {
+    match &expr.kind {
+        ExprKind::Number(_) | ExprKind::Var(_) => {}
+        ExprKind::Unary { val, .. } => visitor.visit_expr(val),
+        ExprKind::Binary { left, right, .. } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        ExprKind::Call { args, .. } => {
+            for arg in args {
+                visitor.visit_expr(arg);
+            }
+        }
+        ExprKind::If { cond, then, else_ } => {
+            visitor.visit_expr(cond);
+            visitor.visit_expr(then);
+            if let Some(else_) = else_ {
+                visitor.visit_expr(else_);
+            }
+        }
+        ExprKind::For {
+            start,
+            end,
+            step,
+            body,
+            ..
+        } => {
+            visitor.visit_expr(start);
+            visitor.visit_expr(end);
+            if let Some(step) = step {
+                visitor.visit_expr(step);
+            }
+            visitor.visit_expr(body);
+        }
+        ExprKind::While { cond, body } => {
+            visitor.visit_expr(cond);
+            visitor.visit_expr(body);
+        }
+        ExprKind::Block { exprs } => {
+            for expr in exprs {
+                visitor.visit_expr(expr);
+            }
+        }
+        ExprKind::Let { vars, body } => {
+            for var in vars {
+                if let Some(val) = &var.val {
+                    visitor.visit_expr(val);
+                }
+            }
+            visitor.visit_expr(body);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse::Parser, source::FileId};
+    use lasso::ThreadedRodeo;
+    use std::sync::Arc;
+
+    #[derive(Default)]
+    struct CallCounter {
+        calls: usize,
+    }
+
+    impl Visitor for CallCounter {
+        fn visit_expr(&mut self, expr: &Expr) {
+            if let ExprKind::Call { .. } = expr.kind {
+                self.calls += 1;
+            }
+            walk_expr(self, expr)
+        }
+    }
+
+    #[test]
+    fn count_calls() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let code = "def f(x) if foo(x) then bar(baz(x), 1) else x; extern foo(x); f(qux())";
+        let items = Parser::new(rodeo, code, FileId::default()).parse().unwrap();
+
+        let mut counter = CallCounter::default();
+        for item in items.iter() {
+            counter.visit_item(item);
+        }
+        assert_eq!(counter.calls, 5);
+    }
+
+    fn parse(code: &str) -> Vec<Item> {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        Parser::new(rodeo, code, FileId::default()).parse().unwrap()
+    }
+
+    fn all_eq_ignore_span(a: &[Item], b: &[Item]) -> bool {
+        all_eq(a, b, Item::eq_ignore_span)
+    }
+
+    #[test]
+    fn eq_ignore_span() {
+        let compact = "def f(x) if x then var y = 1 in { y; f(x - 1) } else 0; var g = 2;";
+        let spaced = "
+            def f(x)
+                if x then
+                    var y = 1 in {
+                        y;
+                        f(x - 1)
+                    }
+                else 0;
+            var g = 2;";
+        let (compact, spaced) = (parse(compact), parse(spaced));
+        assert_ne!(compact, spaced);
+        assert!(all_eq_ignore_span(&compact, &spaced));
+
+        // Parenthesis only change the spans.
+        let a = parse("def f(x) x + (1 * x); extern sin(x ...); def binary | 5 (a b) a;");
+        let b = parse("def f(x) (x) + 1 * x; extern sin(x ...); def binary | 5 (a b) a;");
+        assert!(all_eq_ignore_span(&a, &b));
+    }
+
+    #[test]
+    fn ne_ignore_span() {
+        let differs = |a: &str, b: &str| !all_eq_ignore_span(&parse(a), &parse(b));
+        assert!(differs("def f(x) x + 1;", "def f(x) x - 1;"));
+        assert!(differs("def f(x) x;", "def g(x) x;"));
+        assert!(differs("def f(x y) x;", "def f(x) x;"));
+        assert!(differs(
+            "def f(x) if x then 1;",
+            "def f(x) if x then 1 else 0;"
+        ));
+        assert!(differs("var g;", "var g = 0;"));
+        assert!(differs("extern f(x);", "extern f(x ...);"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let code =
+            "# Loops.\ndef f(x) for i = 1, x in var y = 2.5 in foo(i, y) + 1; extern foo(a b)";
+        let items = Parser::new(rodeo, code, FileId::default()).parse().unwrap();
+
+        let json = serde_json::to_string(&items).unwrap();
+        let parsed: Vec<Item> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, items);
+    }
+}
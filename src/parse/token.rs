@@ -1,10 +1,16 @@
 use crate::span::Span;
 use logos::{Lexer, Logos};
+use smol_str::SmolStr;
 use std::fmt;
 
 #[derive(Logos, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Kind {
-    #[regex("#[^\n]*")]
+    /// A line comment starting with `#`, or a block comment
+    /// delimited by `#*` and `*#`.
+    ///
+    /// Block comments can not be nested, the first `*#` always ends the comment.
+    #[regex("#([^*\n][^\n]*)?")]
+    #[token("#*", block_comment)]
     Comment,
 
     #[token("def")]
@@ -15,6 +21,8 @@ pub enum Kind {
     If,
     #[token("for")]
     For,
+    #[token("while")]
+    While,
     #[token("var")]
     Var,
     #[token("then")]
@@ -27,57 +35,104 @@ pub enum Kind {
     Unary,
     #[token("in")]
     In,
+    #[token("import")]
+    Import,
 
     #[token("(")]
     LeftParen,
     #[token(")")]
     RightParen,
+    #[token("{")]
+    LeftBrace,
+    #[token("}")]
+    RightBrace,
     #[token(",")]
     Comma,
     #[token(";")]
     Semicolon,
+    #[token("...")]
+    Ellipsis,
+    #[token("->")]
+    Arrow,
 
     #[regex("[a-zA-Z][a-zA-Z0-9]*")]
     Identifier,
     #[regex(r"[0-9]*\.?[0-9]+")]
     Number,
+    /// A string literal, which can not contain escapes or line breaks.
+    #[regex("\"[^\"\n]*\"")]
+    String,
     // FIXME: This is probably bad, but that's how Kaleidoscope is made.
     // Probably replace it with a proper regex to only match specific operators.
+    #[token("<=")]
+    #[token(">=")]
+    #[token("==")]
+    #[token("!=")]
+    #[token("&&")]
+    #[token("||")]
     #[regex(".", priority = 0)]
     Operator,
 
     #[error]
-    #[regex(r"[ \t\n\f]+", logos::skip)]
+    #[regex(r"[ \t\r\n\f]+", logos::skip)]
     Error,
 }
 
+/// Kinds are displayed the way they are used in diagnostics.
+///
+/// Tokens that always have the same text are quoted, while kinds
+/// that describe a category of tokens are displayed as plain words.
 impl fmt::Display for Kind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let repr = match self {
             Kind::Comment => "comment",
-            Kind::Def => "def",
-            Kind::Extern => "extern",
-            Kind::If => "if",
-            Kind::Then => "then",
-            Kind::Else => "else",
-            Kind::Binary => "binary",
-            Kind::Unary => "unary",
-            Kind::LeftParen => "(",
-            Kind::RightParen => ")",
-            Kind::Comma => ",",
+            Kind::Def => "'def'",
+            Kind::Extern => "'extern'",
+            Kind::If => "'if'",
+            Kind::Then => "'then'",
+            Kind::Else => "'else'",
+            Kind::Binary => "'binary'",
+            Kind::Unary => "'unary'",
+            Kind::LeftParen => "'('",
+            Kind::RightParen => "')'",
+            Kind::LeftBrace => "'{'",
+            Kind::RightBrace => "'}'",
+            Kind::Comma => "','",
             Kind::Identifier => "identifier",
             Kind::Number => "number",
+            Kind::String => "string",
             Kind::Operator => "operator",
-            Kind::Error => "error",
-            Kind::For => "for",
-            Kind::In => "in",
-            Kind::Var => "var",
-            Kind::Semicolon => ";",
+            Kind::Error => "invalid token",
+            Kind::For => "'for'",
+            Kind::While => "'while'",
+            Kind::In => "'in'",
+            Kind::Import => "'import'",
+            Kind::Var => "'var'",
+            Kind::Semicolon => "';'",
+            Kind::Ellipsis => "'...'",
+            Kind::Arrow => "'->'",
         };
         write!(f, "{}", repr)
     }
 }
 
+/// Lexes the rest of a block comment after the opening `#*`.
+///
+/// If the comment is never closed, the rest of the input is consumed
+/// and an `Error` token is produced.
+fn block_comment(lex: &mut Lexer<'_, Kind>) -> bool {
+    match lex.remainder().find("*#") {
+        Some(end) => {
+            lex.bump(end + 2);
+            true
+        }
+        None => {
+            lex.bump(lex.remainder().len());
+            false
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Token<'input> {
     pub span: Span,
@@ -85,15 +140,53 @@ pub struct Token<'input> {
     pub slice: &'input str,
 }
 
+impl Token<'_> {
+    /// Checks if this token is a block comment that was never closed.
+    pub fn is_unterminated_comment(&self) -> bool {
+        self.kind == Kind::Error && self.slice.starts_with("#*")
+    }
+}
+
+/// A `Token` that owns its text, so it can be stored outside of the source code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedToken {
+    pub span: Span,
+    pub kind: Kind,
+    pub slice: SmolStr,
+}
+
+impl From<Token<'_>> for OwnedToken {
+    fn from(token: Token<'_>) -> Self {
+        Self {
+            span: token.span,
+            kind: token.kind,
+            slice: token.slice.into(),
+        }
+    }
+}
+
+/// An iterator over the tokens of the source code.
+///
+/// Comments are skipped, unless the stream was created using `with_comments`.
 #[derive(Clone)]
 pub struct TokenStream<'input> {
     tokens: Lexer<'input, Kind>,
+    keep_comments: bool,
 }
 
 impl<'input> TokenStream<'input> {
     pub fn new(src: &'input str) -> Self {
         Self {
             tokens: Kind::lexer(src),
+            keep_comments: false,
+        }
+    }
+
+    /// Creates a `TokenStream` that also yields `Comment` tokens.
+    pub fn with_comments(src: &'input str) -> Self {
+        Self {
+            tokens: Kind::lexer(src),
+            keep_comments: true,
         }
     }
 }
@@ -105,7 +198,7 @@ impl<'input> Iterator for TokenStream<'input> {
         let kind = self.tokens.next()?;
         let span = self.tokens.span().into();
         let slice = self.tokens.slice();
-        if matches!(kind, Kind::Comment) {
+        if kind == Kind::Comment && !self.keep_comments {
             return self.next();
         }
         Some(Token { span, kind, slice })
@@ -135,4 +228,111 @@ mod tests {
     fn test_operator() {
         lex_assert("$-+/*", [Kind::Operator].repeat(5));
     }
+
+    #[test]
+    fn test_compound_operator() {
+        lex_assert(
+            "a <= b",
+            [Kind::Identifier, Kind::Operator, Kind::Identifier],
+        );
+
+        let slices = TokenStream::new("a <= b >= c == d != e && f || g | h")
+            .filter(|token| token.kind == Kind::Operator)
+            .map(|token| token.slice)
+            .collect::<Vec<_>>();
+        assert_eq!(slices, ["<=", ">=", "==", "!=", "&&", "||", "|"]);
+    }
+
+    #[test]
+    fn test_line_comment() {
+        lex_assert(
+            "1 # comment\n2",
+            [Kind::Number, Kind::Comment, Kind::Number],
+        );
+    }
+
+    #[test]
+    fn test_block_comment() {
+        lex_assert(
+            "1 #* comment *# 2",
+            [Kind::Number, Kind::Comment, Kind::Number],
+        );
+        lex_assert(
+            "1 #* multi\nline\ncomment *# 2",
+            [Kind::Number, Kind::Comment, Kind::Number],
+        );
+    }
+
+    #[test]
+    fn test_string() {
+        let code = "import \"lib.k\";";
+        lex_assert(code, [Kind::Import, Kind::String, Kind::Semicolon]);
+        lex_assert("\"\"", [Kind::String]);
+    }
+
+    #[test]
+    fn test_crlf() {
+        lex_assert(
+            "1 # comment\r\n2\r\n",
+            [Kind::Number, Kind::Comment, Kind::Number],
+        );
+    }
+
+    #[test]
+    fn test_skip_comments() {
+        let kinds = |stream: TokenStream<'_>| stream.map(|t| t.kind).collect::<Vec<_>>();
+        let code = "1 #* comment *# 2";
+
+        assert_eq!(kinds(TokenStream::new(code)), [Kind::Number, Kind::Number]);
+        assert_eq!(
+            kinds(TokenStream::with_comments(code)),
+            [Kind::Number, Kind::Comment, Kind::Number]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        lex_assert("1 #* comment", [Kind::Number, Kind::Error]);
+
+        let token = TokenStream::new("1 #* comment").nth(1).unwrap();
+        assert!(token.is_unterminated_comment());
+        assert_eq!(token.span, Span::new(2, 12));
+    }
+
+    #[test]
+    fn test_display() {
+        let fixed = [
+            (Kind::Def, "def"),
+            (Kind::Extern, "extern"),
+            (Kind::If, "if"),
+            (Kind::For, "for"),
+            (Kind::While, "while"),
+            (Kind::Var, "var"),
+            (Kind::Then, "then"),
+            (Kind::Else, "else"),
+            (Kind::Binary, "binary"),
+            (Kind::Unary, "unary"),
+            (Kind::In, "in"),
+            (Kind::Import, "import"),
+            (Kind::LeftParen, "("),
+            (Kind::RightParen, ")"),
+            (Kind::LeftBrace, "{"),
+            (Kind::RightBrace, "}"),
+            (Kind::Comma, ","),
+            (Kind::Semicolon, ";"),
+            (Kind::Ellipsis, "..."),
+            (Kind::Arrow, "->"),
+        ];
+        for (kind, text) in fixed.iter() {
+            lex_assert(text, [*kind]);
+            assert_eq!(kind.to_string(), format!("'{}'", text));
+        }
+
+        assert_eq!(Kind::Comment.to_string(), "comment");
+        assert_eq!(Kind::Identifier.to_string(), "identifier");
+        assert_eq!(Kind::Number.to_string(), "number");
+        assert_eq!(Kind::String.to_string(), "string");
+        assert_eq!(Kind::Operator.to_string(), "operator");
+        assert_eq!(Kind::Error.to_string(), "invalid token");
+    }
 }
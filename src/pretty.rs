@@ -1,4 +1,7 @@
-use crate::parse::ast::{Expr, ExprKind, Item, ItemKind, LetVar};
+use crate::parse::{
+    ast::{Associativity, Expr, ExprKind, Identifier, Item, ItemKind, LetVar, ReturnType},
+    BUILTIN_OPERATORS,
+};
 use lasso::ThreadedRodeo;
 use pretty::{DocAllocator, DocBuilder};
 
@@ -13,6 +16,103 @@ pub trait Pretty {
     where
         D: DocAllocator<'alloc>,
         D::Doc: Clone;
+
+    /// Renders `&self` into a `String` that tries to fit into `width` columns.
+    fn pretty_to_string(&self, rodeo: &ThreadedRodeo, width: usize) -> String {
+        let alloc = pretty::Arena::<()>::new();
+        let mut out = Vec::new();
+        self.pretty(&alloc, rodeo)
+            .1
+            .render(width, &mut out)
+            .expect("failed to render into a vec");
+        String::from_utf8(out).expect("pretty printer produced invalid utf8")
+    }
+}
+
+/// Returns the precedence of the given builtin binary operator.
+fn precedence(op: &str) -> Option<i32> {
+    BUILTIN_OPERATORS
+        .iter()
+        .find(|(builtin, _)| *builtin == op)
+        .map(|(_, prec)| *prec)
+}
+
+/// Formats the value of a number literal.
+///
+/// This is the shortest form that parses back to the same value. It never uses
+/// an exponent, because the lexer doesn't support them, and whole numbers
+/// don't have a decimal point, so `2.0` is formatted as `2`.
+fn format_number(x: f64) -> String {
+    format!("{}", x)
+}
+
+/// Checks if the expression ends with an expression like `if` that
+/// would swallow everything that follows it.
+fn ends_open(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::If { .. }
+        | ExprKind::For { .. }
+        | ExprKind::While { .. }
+        | ExprKind::Let { .. } => true,
+        ExprKind::Binary { right, .. } => ends_open(right),
+        ExprKind::Unary { val, .. } => ends_open(val),
+        _ => false,
+    }
+}
+
+/// Checks if the expression ends with an `if` without an `else`,
+/// which would take the `else` of an enclosing `if`.
+fn ends_with_open_if(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::If { else_: None, .. } => true,
+        ExprKind::If {
+            else_: Some(last), ..
+        }
+        | ExprKind::For { body: last, .. }
+        | ExprKind::While { body: last, .. }
+        | ExprKind::Let { body: last, .. }
+        | ExprKind::Binary { right: last, .. }
+        | ExprKind::Unary { val: last, .. } => ends_with_open_if(last),
+        _ => false,
+    }
+}
+
+/// Checks if the operand of the binary operator `parent` must be wrapped in
+/// parenthesis to keep the structure of the expression.
+///
+/// Operands using operators with an unknown precedence are always wrapped.
+fn needs_parens(operand: &Expr, parent: &str, is_right: bool) -> bool {
+    if !is_right && ends_open(operand) {
+        return true;
+    }
+
+    match &operand.kind {
+        // All builtin operators are left associative, so a right operand
+        // with the same precedence has to be wrapped.
+        ExprKind::Binary { op, .. } => match (precedence(op), precedence(parent)) {
+            (Some(child), Some(parent)) if is_right => child <= parent,
+            (Some(child), Some(parent)) => child < parent,
+            _ => true,
+        },
+        _ => false,
+    }
+}
+
+/// Wraps the document in parenthesis if `wrap` is true.
+fn parens<'alloc, D>(
+    alloc: &'alloc D,
+    doc: DocBuilder<'alloc, D>,
+    wrap: bool,
+) -> DocBuilder<'alloc, D>
+where
+    D: DocAllocator<'alloc>,
+    D::Doc: Clone,
+{
+    if wrap {
+        alloc.text("(").append(doc).append(alloc.text(")"))
+    } else {
+        doc
+    }
 }
 
 impl Pretty for Expr {
@@ -40,22 +140,36 @@ impl Pretty for ExprKind {
         D::Doc: Clone,
     {
         match self {
-            ExprKind::Number(x) => alloc.as_string(x),
-            ExprKind::Var(name) => alloc.as_string(rodeo.resolve(&name.spur)),
+            ExprKind::Number(x) => alloc.text(format_number(x.into_inner())),
+            ExprKind::Var(name) => name.pretty(alloc, rodeo),
             ExprKind::Unary { op, val } => {
-                alloc.as_string(op).append(val.pretty(alloc, rodeo)).group()
+                // A minus in front of a number would be parsed as a negative literal.
+                let wrap = match &val.kind {
+                    ExprKind::Binary { .. } => true,
+                    ExprKind::Number(_) => *op == '-',
+                    _ => false,
+                };
+                alloc
+                    .as_string(op)
+                    .append(parens(alloc, val.pretty(alloc, rodeo), wrap))
+                    .group()
+            }
+            ExprKind::Binary {
+                left, op, right, ..
+            } => {
+                let left_parens = needs_parens(left, op, false);
+                let right_parens = needs_parens(right, op, true);
+                parens(alloc, left.pretty(alloc, rodeo), left_parens)
+                    .append(alloc.space())
+                    .append(alloc.as_string(op))
+                    .append(alloc.space())
+                    .append(parens(alloc, right.pretty(alloc, rodeo), right_parens))
+                    .group()
             }
-            ExprKind::Binary { left, op, right } => left
-                .pretty(alloc, rodeo)
-                .append(alloc.space())
-                .append(alloc.as_string(op))
-                .append(alloc.space())
-                .append(right.pretty(alloc, rodeo))
-                .group(),
             ExprKind::Call { callee, args } => {
                 let separator = alloc.text(",").append(alloc.space());
-                alloc
-                    .as_string(rodeo.resolve(&callee.spur))
+                callee
+                    .pretty(alloc, rodeo)
                     .append(alloc.text("("))
                     .append(alloc.intersperse(
                         args.into_iter().map(|expr| expr.pretty(alloc, rodeo)),
@@ -64,30 +178,81 @@ impl Pretty for ExprKind {
                     .append(alloc.text(")"))
                     .group()
             }
-            ExprKind::If { cond, then, else_ } => alloc
-                .text("if")
+            ExprKind::If { cond, then, else_ } => {
+                let wrap = else_.is_some() && ends_with_open_if(then);
+                let then = parens(alloc, then.pretty(alloc, rodeo), wrap);
+                alloc
+                    .text("if")
+                    .append(alloc.space())
+                    .append(cond.pretty(alloc, rodeo))
+                    .append(alloc.space())
+                    .append(alloc.text("then"))
+                    .append(alloc.hardline().append(then).nest(2))
+                    .append(match else_ {
+                        Some(else_) => alloc
+                            .hardline()
+                            .append(alloc.text("else"))
+                            .append(alloc.hardline().append(else_.pretty(alloc, rodeo)).nest(2)),
+                        None => alloc.nil(),
+                    })
+                    .group()
+            }
+            ExprKind::For {
+                var,
+                start,
+                end,
+                step,
+                body,
+            } => alloc
+                .text("for")
+                .append(alloc.space())
+                .append(var.pretty(alloc, rodeo))
+                .append(alloc.space())
+                .append(alloc.text("="))
+                .append(alloc.space())
+                .append(start.pretty(alloc, rodeo))
+                .append(alloc.text(","))
+                .append(alloc.space())
+                .append(end.pretty(alloc, rodeo))
+                .append(match step {
+                    Some(step) => alloc
+                        .text(",")
+                        .append(alloc.space())
+                        .append(step.pretty(alloc, rodeo)),
+                    None => alloc.nil(),
+                })
+                .append(alloc.space())
+                .append(alloc.text("in"))
+                .append(alloc.hardline().append(body.pretty(alloc, rodeo)).nest(2))
+                .group(),
+            ExprKind::While { cond, body } => alloc
+                .text("while")
                 .append(alloc.space())
                 .append(cond.pretty(alloc, rodeo))
                 .append(alloc.space())
-                .append(alloc.text("then"))
-                .append(alloc.hardline().append(then.pretty(alloc, rodeo)).nest(2))
-                .append(alloc.hardline().append(alloc.text("else")))
-                .append(alloc.hardline().append(else_.pretty(alloc, rodeo)).nest(2))
+                .append(alloc.text("in"))
+                .append(alloc.hardline().append(body.pretty(alloc, rodeo)).nest(2))
                 .group(),
-            ExprKind::For { .. } => todo!(),
+            ExprKind::Block { exprs } if exprs.is_empty() => alloc.text("{}"),
+            ExprKind::Block { exprs } => {
+                let separator = alloc.text(";").append(alloc.hardline());
+                alloc
+                    .text("{")
+                    .append(
+                        alloc
+                            .hardline()
+                            .append(alloc.intersperse(
+                                exprs.iter().map(|expr| expr.pretty(alloc, rodeo)),
+                                separator,
+                            ))
+                            .nest(2),
+                    )
+                    .append(alloc.hardline())
+                    .append(alloc.text("}"))
+                    .group()
+            }
             ExprKind::Let { vars, body } => {
-                let vars = vars.into_iter().map(|LetVar { name, val }| {
-                    let doc = alloc.as_string(rodeo.resolve(&name.spur));
-                    if let Some(val) = val {
-                        doc.append(alloc.space())
-                            .append(alloc.text("="))
-                            .append(alloc.space())
-                            .append(val.pretty(alloc, rodeo))
-                            .group()
-                    } else {
-                        doc.group()
-                    }
-                });
+                let vars = vars.iter().map(|var| var.pretty(alloc, rodeo));
                 let separator = alloc.text(",").append(alloc.space());
                 alloc
                     .text("var")
@@ -103,6 +268,43 @@ impl Pretty for ExprKind {
     }
 }
 
+impl Pretty for Identifier {
+    fn pretty<'alloc, D>(
+        &'alloc self,
+        alloc: &'alloc D,
+        rodeo: &ThreadedRodeo,
+    ) -> DocBuilder<'alloc, D>
+    where
+        D: DocAllocator<'alloc>,
+        D::Doc: Clone,
+    {
+        alloc.as_string(rodeo.resolve(&self.spur))
+    }
+}
+
+impl Pretty for LetVar {
+    fn pretty<'alloc, D>(
+        &'alloc self,
+        alloc: &'alloc D,
+        rodeo: &ThreadedRodeo,
+    ) -> DocBuilder<'alloc, D>
+    where
+        D: DocAllocator<'alloc>,
+        D::Doc: Clone,
+    {
+        let name = self.name.pretty(alloc, rodeo);
+        match &self.val {
+            Some(val) => name
+                .append(alloc.space())
+                .append(alloc.text("="))
+                .append(alloc.space())
+                .append(val.pretty(alloc, rodeo))
+                .group(),
+            None => name.group(),
+        }
+    }
+}
+
 impl Pretty for Item {
     fn pretty<'alloc, D>(
         &'alloc self,
@@ -113,7 +315,15 @@ impl Pretty for Item {
         D: DocAllocator<'alloc>,
         D::Doc: Clone,
     {
-        self.kind.pretty(alloc, rodeo)
+        let doc = self.doc.iter().flat_map(|doc| doc.split('\n')).map(|line| {
+            let comment = if line.is_empty() {
+                "#".to_string()
+            } else {
+                format!("# {}", line)
+            };
+            alloc.text(comment).append(alloc.hardline())
+        });
+        alloc.concat(doc).append(self.kind.pretty(alloc, rodeo))
     }
 }
 
@@ -133,15 +343,12 @@ impl Pretty for ItemKind {
                 alloc
                     .text("def")
                     .append(alloc.space())
-                    .append(alloc.as_string(rodeo.resolve(&name.spur)))
+                    .append(name.pretty(alloc, rodeo))
                     .append(alloc.text("("))
-                    .append(
-                        alloc.intersperse(
-                            args.into_iter()
-                                .map(|name| alloc.as_string(rodeo.resolve(&name.spur))),
-                            separator,
-                        ),
-                    )
+                    .append(alloc.intersperse(
+                        args.into_iter().map(|name| name.pretty(alloc, rodeo)),
+                        separator,
+                    ))
                     .append(alloc.text(")"))
                     .group()
                     .append(
@@ -152,28 +359,59 @@ impl Pretty for ItemKind {
                             .nest(2),
                     )
             }
-            ItemKind::Extern { name, args } => {
+            ItemKind::Extern {
+                name,
+                args,
+                variadic,
+                ret,
+            } => {
                 let separator = alloc.space();
+                let ellipsis = match (variadic, args.is_empty()) {
+                    (false, _) => alloc.nil(),
+                    (true, true) => alloc.text("..."),
+                    (true, false) => alloc.text(" ..."),
+                };
                 alloc
                     .text("extern")
                     .append(alloc.space())
-                    .append(alloc.as_string(rodeo.resolve(&name.spur)))
+                    .append(name.pretty(alloc, rodeo))
                     .append(alloc.text("("))
-                    .append(
-                        alloc.intersperse(
-                            args.into_iter()
-                                .map(|name| alloc.as_string(rodeo.resolve(&name.spur))),
-                            separator,
-                        ),
-                    )
+                    .append(alloc.intersperse(
+                        args.into_iter().map(|name| name.pretty(alloc, rodeo)),
+                        separator,
+                    ))
+                    .append(ellipsis)
                     .append(alloc.text(")"))
+                    .append(match ret {
+                        ReturnType::Double => alloc.nil(),
+                        ret => alloc.text(" -> ").append(alloc.text(ret.name())),
+                    })
                     .append(alloc.text(";"))
                     .group()
             }
+            ItemKind::Global { name, init } => alloc
+                .text("var")
+                .append(alloc.space())
+                .append(name.pretty(alloc, rodeo))
+                .append(match init {
+                    Some(init) => alloc
+                        .space()
+                        .append(alloc.text("="))
+                        .append(alloc.space())
+                        .append(init.pretty(alloc, rodeo)),
+                    None => alloc.nil(),
+                })
+                .append(alloc.text(";"))
+                .group(),
+            ItemKind::Import { path } => alloc
+                .text("import")
+                .append(alloc.space())
+                .append(alloc.text(format!("\"{}\";", path))),
             ItemKind::Operator {
                 op,
                 prec,
                 is_binary,
+                assoc,
                 body,
                 args,
             } => {
@@ -191,14 +429,15 @@ impl Pretty for ItemKind {
                     } else {
                         alloc.nil()
                     })
+                    .append(match assoc {
+                        Associativity::Right => alloc.text("right").append(alloc.space()),
+                        Associativity::Left => alloc.nil(),
+                    })
                     .append(alloc.text("("))
-                    .append(
-                        alloc.intersperse(
-                            args.into_iter()
-                                .map(|name| alloc.as_string(rodeo.resolve(&name.spur))),
-                            separator,
-                        ),
-                    )
+                    .append(alloc.intersperse(
+                        args.into_iter().map(|name| name.pretty(alloc, rodeo)),
+                        separator,
+                    ))
                     .append(alloc.text(")"))
                     .group()
                     .append(
@@ -212,3 +451,209 @@ impl Pretty for ItemKind {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse::Parser, source::FileId};
+    use std::sync::Arc;
+
+    fn parse(rodeo: &Arc<ThreadedRodeo>, code: &str) -> Vec<Item> {
+        Parser::new(Arc::clone(rodeo), code, FileId::default())
+            .parse()
+            .unwrap()
+    }
+
+    fn print(rodeo: &ThreadedRodeo, items: &[Item]) -> String {
+        items
+            .iter()
+            .map(|item| item.pretty_to_string(rodeo, 50) + "\n")
+            .collect()
+    }
+
+    fn pretty(code: &str) -> String {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        print(&rodeo, &parse(&rodeo, code))
+    }
+
+    /// Checks that printing the code doesn't change its structure,
+    /// and that the printed code is printed the same way again.
+    fn assert_round_trip(code: &str) {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let items = parse(&rodeo, code);
+        let first = print(&rodeo, &items);
+
+        let reparsed = parse(&rodeo, &first);
+        assert_eq!(items.len(), reparsed.len(), "{}", first);
+        for (item, other) in items.iter().zip(&reparsed) {
+            assert!(item.eq_ignore_span(other), "{}", first);
+        }
+        assert_eq!(first, print(&rodeo, &reparsed));
+    }
+
+    #[test]
+    fn identifier_to_string() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let expr = Parser::new(Arc::clone(&rodeo), "foo(x)", FileId::default())
+            .parse_expr()
+            .unwrap();
+        match &expr.kind {
+            ExprKind::Call { callee, .. } => assert_eq!(callee.pretty_to_string(&rodeo, 50), "foo"),
+            kind => panic!("expected call, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn let_var_to_string() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let expr = Parser::new(
+            Arc::clone(&rodeo),
+            "var x = 1 + 2, y in x",
+            FileId::default(),
+        )
+        .parse_expr()
+        .unwrap();
+        let vars = match &expr.kind {
+            ExprKind::Let { vars, .. } => vars
+                .iter()
+                .map(|var| var.pretty_to_string(&rodeo, 50))
+                .collect::<Vec<_>>(),
+            kind => panic!("expected var, found {:?}", kind),
+        };
+        assert_eq!(vars, ["x = 1 + 2", "y"]);
+    }
+
+    #[test]
+    fn expr_to_string() {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let expr = Parser::new(
+            Arc::clone(&rodeo),
+            "if x < 1 then foo(x) else 2",
+            FileId::default(),
+        )
+        .parse_expr()
+        .unwrap();
+        assert_eq!(
+            expr.pretty_to_string(&rodeo, 50),
+            "if x < 1 then\n  foo(x)\nelse\n  2"
+        );
+    }
+
+    fn pretty_expr(code: &str) -> String {
+        let rodeo = Arc::new(ThreadedRodeo::new());
+        let mut parser = Parser::new(Arc::clone(&rodeo), code, FileId::default());
+        parser.parse_expr().unwrap().pretty_to_string(&rodeo, 50)
+    }
+
+    #[test]
+    fn binary_parenthesis() {
+        assert_eq!(pretty_expr("(a + b) * c"), "(a + b) * c");
+        assert_eq!(pretty_expr("a + (b * c)"), "a + b * c");
+        assert_eq!(pretty_expr("(a - b) - c"), "a - b - c");
+        assert_eq!(pretty_expr("a - (b - c)"), "a - (b - c)");
+        assert_eq!(pretty_expr("(a < b) == (c < d)"), "a < b == (c < d)");
+        assert_eq!(pretty_expr("-(a + b)"), "-(a + b)");
+    }
+
+    #[test]
+    fn round_trip_mixed_precedence() {
+        assert_round_trip("def f(a b c) (a + b) * c;");
+        assert_round_trip("def f(a b c) a * (b + c) / (a - b);");
+        assert_round_trip("def f(a b c) a - (b - c) - (a / (b * c));");
+        assert_round_trip("def f(a b) a = (b < 2) + (b == a);");
+        assert_round_trip("def f(a b) (if a then b else 2) + 3;");
+        assert_round_trip("def binary : 5 (a b) a; def f(a b) a : (b + 1);");
+        assert_round_trip("def binary ^ 50 right (a b) a; def f(a b) (a ^ b) ^ a ^ b;");
+    }
+
+    #[test]
+    fn round_trip_parenthesis() {
+        assert_round_trip("def f(a b c) ((a)) + (b + (c));");
+        assert_round_trip("def f(a b c) -(a - b) * -(-1) + (-c);");
+        assert_round_trip("def f(a b) (a < b) < ((a == b) = 1);");
+        assert_round_trip("def f(a b) foo((a + b) * 2, (if a then b else 1) - 1);");
+        assert_round_trip("def f(a b) (for i = 0, i < a in b) + (var x = a in x) * 2;");
+        assert_round_trip("def unary ~ (v) 0 - v; def f(a b) ~(a + b) - ~a;");
+
+        // Redundant parenthesis are not part of the AST, so they aren't printed.
+        assert_eq!(pretty("def f(a b) ((a)) + (b);"), "def f(a b)\n  a + b;\n");
+    }
+
+    #[test]
+    fn round_trip_doc_comments() {
+        let code = "# Adds two numbers.\n#\n#   a + b\ndef add(a b) a + b;";
+        assert_eq!(
+            pretty(code),
+            "# Adds two numbers.\n#\n#   a + b\ndef add(a b)\n  a + b;\n"
+        );
+        assert_round_trip(code);
+        assert_round_trip("#* Block\ncomment *# extern sin(x);");
+        assert_round_trip("# The counter\nvar count = 0;");
+    }
+
+    #[test]
+    fn round_trip_if_without_else() {
+        assert_round_trip("def f(x) if x then 1;");
+        assert_eq!(pretty_expr("if x then 1"), "if x then\n  1");
+
+        let code = "if a then (if b then 1) else 2";
+        assert_eq!(
+            pretty_expr(code),
+            "if a then\n  (if b then\n    1)\nelse\n  2"
+        );
+        assert_eq!(pretty_expr(&pretty_expr(code)), pretty_expr(code));
+    }
+
+    #[test]
+    fn round_trip_for() {
+        assert_round_trip("def f(x) for i = 0, i < x in x;");
+        assert_round_trip("def f(x) for i = 0, i < x, 2 in x;");
+    }
+
+    #[test]
+    fn round_trip_while() {
+        assert_round_trip("def f(x) while x < 10 in foo(x);");
+    }
+
+    #[test]
+    fn round_trip_block() {
+        assert_round_trip("def f(x) { x = x + 1; foo(x); x };");
+        assert_round_trip("def f(x) {};");
+    }
+
+    #[test]
+    fn round_trip_numbers() {
+        assert_eq!(pretty_expr("2.0 + 0.50 * 007"), "2 + 0.5 * 7");
+        assert_eq!(pretty_expr("-3 - -.25"), "-3 - -0.25");
+        assert_round_trip("def f(x) x * 100000000000000000000 + 0.000001;");
+        assert_round_trip("def f() -0 - -1.5;");
+
+        assert_eq!(format_number(1e21), "1000000000000000000000");
+        assert_eq!(format_number(-0.0), "-0");
+    }
+
+    #[test]
+    fn round_trip_import() {
+        assert_round_trip("import \"lib.k\";");
+        assert_eq!(pretty("import   \"lib.k\" ;"), "import \"lib.k\";\n");
+    }
+
+    #[test]
+    fn round_trip_variadic_extern() {
+        assert_round_trip("extern printd(x ...);");
+        assert_round_trip("extern printd(...);");
+    }
+
+    #[test]
+    fn round_trip_typed_extern() {
+        assert_round_trip("extern putchard(x) -> void;");
+        assert_round_trip("extern printf(fmt ...) -> void;");
+        assert_eq!(pretty("extern sin(x) -> double;"), "extern sin(x);\n");
+    }
+
+    #[test]
+    fn round_trip_global() {
+        assert_round_trip("var x = 1 + 2;");
+        assert_round_trip("var y;");
+    }
+}
@@ -7,13 +7,27 @@ mod commands;
 mod helper;
 
 use self::helper::ReplHelper;
-use inkwell::{context::Context, passes::PassManager};
+use inkwell::{context::Context, OptimizationLevel};
 use kaleidoscope::{
-    codegen::Compiler, error::emit, parse::FrontendDatabase, source::File, CompilerDatabase,
-    SourceDatabase,
+    analysis::resolve_names,
+    codegen::{create_pass_manager, Compiler},
+    error::{emit, RunError},
+    parse::{
+        ast::{Item, ItemKind},
+        FrontendDatabase,
+    },
+    source::{File, FileId},
+    CompilerDatabase, Diagnostic,
 };
+use lasso::ThreadedRodeo;
 use rustyline::{error::ReadlineError, Cmd, CompletionType, Config, EditMode, Editor, KeyPress};
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+    rc::Rc,
+    sync::Arc,
+};
 
 /// The prefix to execute commands.
 const PREFIX: char = '.';
@@ -23,6 +37,15 @@ pub struct Repl {
     editor: Editor<ReplHelper>,
     db: CompilerDatabase,
     commands: HashMap<&'static str, fn(&mut Repl, &str)>,
+    /// All definitions of this session, which are compiled before every input.
+    items: Vec<Item>,
+    /// The optimization level that is used for codegen and the JIT.
+    opt_level: OptimizationLevel,
+    /// The file that is used to persist the history.
+    history_path: Option<PathBuf>,
+    /// The names of all functions and externs of this session,
+    /// which are shared with the helper for completion.
+    names: Rc<RefCell<BTreeSet<String>>>,
 }
 
 impl Repl {
@@ -38,7 +61,8 @@ impl Repl {
 
         let commands = commands::default_commands();
 
-        let helper = ReplHelper::new(commands.keys().copied().collect());
+        let names = Rc::new(RefCell::new(BTreeSet::new()));
+        let helper = ReplHelper::new(commands.keys().copied().collect(), Rc::clone(&names));
         editor.set_helper(Some(helper));
 
         editor.bind_sequence(KeyPress::Up, Cmd::LineUpOrPreviousHistory(1));
@@ -46,34 +70,32 @@ impl Repl {
 
         let mut db = CompilerDatabase::default();
         db.set_rodeo(Arc::new(Default::default()));
+        db.set_predefined_operators(Arc::new(Vec::new()));
         Self {
             editor,
             db,
             commands,
+            items: Vec::new(),
+            opt_level: OptimizationLevel::None,
+            history_path: default_history_path(),
+            names,
         }
     }
 
-    fn history_path(&self) -> Option<PathBuf> {
-        let mut path = dirs::data_dir()?;
-        path.push("kaleidoscope_history");
-        Some(path)
-    }
-
     fn save_history(&mut self) -> Option<()> {
-        let path = self.history_path()?;
-        self.editor.save_history(&path).ok()
+        let path = self.history_path.as_ref()?;
+        self.editor.save_history(path).ok()
     }
 
     fn load_history(&mut self) -> Option<()> {
-        let path = self.history_path()?;
-        self.editor.load_history(&path).ok()
+        let path = self.history_path.as_ref()?;
+        self.editor.load_history(path).ok()
     }
 
     pub fn run(&mut self) -> rustyline::Result<()> {
         self.load_history();
 
-        let version = env!("CARGO_PKG_VERSION");
-        println!("Kaleidoscope {}", version);
+        println!("{}", crate::version());
         let result = loop {
             let line = self.editor.readline(PROMPT);
             match line {
@@ -107,35 +129,327 @@ impl Repl {
     }
 
     fn execute_code(&mut self, line: String) {
-        let file = File::new(Arc::new("repl".into()), Arc::new(line));
-        let file = self.db.intern_file(file);
-        let ast = match self.db.parse(file) {
-            Ok(ast) => ast,
-            Err(err) => {
-                emit(&self.db, err.into()).expect("failed to emit error");
-                return;
-            }
-        };
+        match self.eval_str(&line) {
+            Ok(None) => emit(&self.db, RunError::NoMain.into()).expect("failed to emit note"),
+            result => self.print_result(result),
+        }
+    }
+
+    /// Executes the given file and prints the result or the error.
+    fn execute_file(&mut self, file: FileId) {
+        let result = self.eval_file(file);
+        self.print_result(result);
+    }
+
+    fn print_result(&self, result: Result<Option<f64>, Diagnostic>) {
+        match result {
+            Ok(Some(result)) => println!("=> {}", result),
+            Ok(None) => {}
+            Err(err) => emit(&self.db, err).expect("failed to emit error"),
+        }
+    }
+
+    /// Evaluates the given code like it was entered into the REPL,
+    /// and returns the result of the `main` function if there is one.
+    ///
+    /// Every input is its own file, even if it spans multiple lines,
+    /// so spans never have to be moved relative to earlier input.
+    pub fn eval_str(&mut self, code: &str) -> Result<Option<f64>, Diagnostic> {
+        let file = File::new(Arc::new("repl".into()), Arc::new(code.into()));
+        let file = self.db.add_file(file);
+        self.eval_file(file)
+    }
+
+    /// Evaluates the given file after all files it imports, as if they
+    /// were loaded using `.load`, and returns the result of the given file.
+    fn eval_file(&mut self, file: FileId) -> Result<Option<f64>, Diagnostic> {
+        // The imports already come in the order they have to be evaluated,
+        // followed by `file` itself.
+        let mut result = None;
+        for file in self.db.load_imports(file)? {
+            result = self.eval_single_file(file)?;
+        }
+        Ok(result)
+    }
+
+    /// Parses, compiles and runs the given file together with all definitions
+    /// of this session, and then adds the new definitions to the session.
+    ///
+    /// Warnings are emitted directly. If there are multiple errors,
+    /// all but the last one are emitted and the last one is returned.
+    fn eval_single_file(&mut self, file: FileId) -> Result<Option<f64>, Diagnostic> {
+        let ast = self.db.parse(file).map_err(Into::<Diagnostic>::into)?;
+
+        for warning in self.db.analyze(file) {
+            emit(&self.db, warning.into()).expect("failed to emit warning");
+        }
 
         let ctx = Context::create();
         let builder = ctx.create_builder();
         let module = ctx.create_module("repl");
 
-        let fpm = PassManager::create(&module);
-        fpm.initialize();
+        let fpm = create_pass_manager(&module, self.opt_level);
+
+        let items = self.session_items(&ast);
+        let mut errors = resolve_names(&self.db.rodeo(), file, &items);
+        if let Some(last) = errors.pop() {
+            for err in errors {
+                emit(&self.db, err.into()).expect("failed to emit error");
+            }
+            return Err(last.into());
+        }
 
         let mut compiler = Compiler::new(file, &ctx, &builder, &fpm, &module, self.db.rodeo());
-        for item in ast.iter() {
-            match compiler.compile_item(&item) {
-                Ok(_) => {}
-                Err(err) => {
-                    emit(&self.db, err.into()).expect("failed to emit error");
-                    return;
-                }
-            };
+        compiler
+            .compile_items(&items)
+            .map_err(Into::<Diagnostic>::into)?;
+        let result = main_result(compiler.run_main(self.opt_level))?;
+
+        self.define(ast);
+        Ok(result)
+    }
+
+    /// Returns all definitions of this session followed by the given items.
+    ///
+    /// Definitions that are redefined by the new items are skipped.
+    fn session_items(&self, new: &[Item]) -> Vec<Item> {
+        let rodeo = self.db.rodeo();
+        let names = new
+            .iter()
+            .map(|item| definition_name(&rodeo, item))
+            .collect::<Vec<_>>();
+        self.items
+            .iter()
+            .filter(|item| !names.contains(&definition_name(&rodeo, item)))
+            .chain(new.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Adds all definitions of the given items to this session, replacing
+    /// previous definitions with the same name.
+    fn define(&mut self, items: Vec<Item>) {
+        let rodeo = self.db.rodeo();
+        for item in items {
+            let name = definition_name(&rodeo, &item);
+            // Top level expressions are not definitions, and the
+            // definitions of imports are already part of the session.
+            if name == "main" || matches!(item.kind, ItemKind::Import { .. }) {
+                continue;
+            }
+
+            self.items
+                .retain(|other| definition_name(&rodeo, other) != name);
+            self.items.push(item);
         }
-        if let Some(result) = compiler.run_main() {
-            println!("=> {}", result);
+        self.update_names();
+        self.update_operators();
+    }
+
+    /// Updates the names that are used for completion to match the
+    /// current definitions.
+    fn update_names(&mut self) {
+        let rodeo = self.db.rodeo();
+        let names = self.items.iter().filter_map(|item| match &item.kind {
+            ItemKind::Function { name, .. }
+            | ItemKind::Extern { name, .. }
+            | ItemKind::Global { name, .. } => Some(rodeo.resolve(&name.spur).to_string()),
+            ItemKind::Operator { .. } | ItemKind::Import { .. } => None,
+        });
+
+        let mut completions = self.names.borrow_mut();
+        completions.clear();
+        completions.extend(names);
+    }
+
+    /// Makes the operators of the current definitions known to the parser,
+    /// so they can be used in the following input.
+    fn update_operators(&mut self) {
+        let operators = self
+            .items
+            .iter()
+            .filter(|item| matches!(item.kind, ItemKind::Operator { .. }))
+            .cloned()
+            .collect();
+        self.db.set_predefined_operators(Arc::new(operators));
+    }
+}
+
+fn default_history_path() -> Option<PathBuf> {
+    let mut path = dirs::data_dir()?;
+    path.push("kaleidoscope_history");
+    Some(path)
+}
+
+/// Returns the name of the LLVM value that is defined by the given item.
+fn definition_name(rodeo: &ThreadedRodeo, item: &Item) -> String {
+    match &item.kind {
+        ItemKind::Function { name, .. }
+        | ItemKind::Extern { name, .. }
+        | ItemKind::Global { name, .. } => rodeo.resolve(&name.spur).to_string(),
+        ItemKind::Operator { op, is_binary, .. } => {
+            let prefix = if *is_binary { "binary" } else { "unary" };
+            format!("{}{}", prefix, op)
         }
+        ItemKind::Import { path } => format!("import {}", path),
+    }
+}
+
+/// Converts the result of `Compiler::run_main` into the result of evaluating code,
+/// which is `None` if there was no `main` function to run.
+fn main_result(result: Result<f64, RunError>) -> Result<Option<f64>, Diagnostic> {
+    match result {
+        Ok(result) => Ok(Some(result)),
+        Err(RunError::NoMain) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempPath;
+    use codespan_reporting::diagnostic::Severity;
+    use kaleidoscope::SourceDatabase;
+    use rustyline::completion::{Candidate, Completer};
+
+    fn is_defined(repl: &Repl, name: &str) -> bool {
+        let rodeo = repl.db.rodeo();
+        repl.items
+            .iter()
+            .any(|item| definition_name(&rodeo, item) == name)
+    }
+
+    #[test]
+    fn reset_removes_definitions() {
+        let mut repl = Repl::new();
+        repl.process_line("def foo() 1;".into());
+        assert!(is_defined(&repl, "foo"));
+
+        repl.process_line(".reset".into());
+        assert!(!is_defined(&repl, "foo"));
+    }
+
+    #[test]
+    fn eval_main() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval_str("def main() 6*7;").unwrap(), Some(42.0));
+        assert_eq!(repl.eval_str("def foo(x) x + 1;").unwrap(), None);
+        assert_eq!(repl.eval_str("foo(2)").unwrap(), Some(3.0));
+
+        let err = repl.eval_str("bar(2)").unwrap_err();
+        assert_eq!(err.message, "unknown function");
+    }
+
+    #[test]
+    fn operators_across_inputs() {
+        let mut repl = Repl::new();
+        repl.eval_str("def binary | 5 (a b) if a then 1 else b;")
+            .unwrap();
+        repl.eval_str("def unary ~ (v) 0 - v;").unwrap();
+        assert_eq!(repl.eval_str("0 | 2").unwrap(), Some(2.0));
+        assert_eq!(repl.eval_str("~3 | 0").unwrap(), Some(1.0));
+
+        // Operators of the session can still be redefined.
+        repl.eval_str("def binary | 5 (a b) a;").unwrap();
+        assert_eq!(repl.eval_str("0 | 2").unwrap(), Some(0.0));
+
+        repl.process_line(".reset".into());
+        assert!(repl.eval_str("0 | 2").is_err());
+    }
+
+    #[test]
+    fn suggest_defined_function() {
+        let mut repl = Repl::new();
+        repl.eval_str("def foo() 1;").unwrap();
+
+        let err = repl.eval_str("fooo()").unwrap_err();
+        assert_eq!(err.message, "unknown function");
+        assert_eq!(err.notes, ["did you mean `foo`?"]);
+    }
+
+    #[test]
+    fn multi_line_error() {
+        let mut repl = Repl::new();
+        let err = repl.eval_str("def f(x)\n  bar(x);").unwrap_err();
+        assert_eq!(err.message, "unknown function");
+
+        // Multi-line input is a single file, so spans are relative to its first line.
+        let label = &err.labels[0];
+        assert_eq!(label.range, 11..17);
+        assert_eq!(repl.db.line_column(label.file_id, 11), Some((1, 2)));
+    }
+
+    #[test]
+    fn main_results() {
+        assert_eq!(main_result(Ok(1.0)).unwrap(), Some(1.0));
+        assert_eq!(main_result(Err(RunError::NoMain)).unwrap(), None);
+
+        let err = main_result(Err(RunError::Jit("oops".into()))).unwrap_err();
+        assert_eq!(err.message, "failed to create the JIT: oops");
+
+        let note: Diagnostic = RunError::NoMain.into();
+        assert_eq!(note.severity, Severity::Note);
+        assert_eq!(note.message, "no `main` function to run");
+    }
+
+    #[test]
+    fn eval_import() {
+        let path = TempPath::new("repl_import.k");
+        std::fs::write(&*path, "def square(x) x * x;").unwrap();
+
+        let mut repl = Repl::new();
+        let code = format!("import \"{}\"; square(3)", path.display());
+        assert_eq!(repl.eval_str(&code).unwrap(), Some(9.0));
+        assert_eq!(repl.eval_str("square(4)").unwrap(), Some(16.0));
+        assert_eq!(repl.items.len(), 1);
+    }
+
+    #[test]
+    fn eval_shared_imports() {
+        let math = TempPath::new("repl_math.k");
+        std::fs::write(&*math, "def square(x) x * x;").unwrap();
+        let util = TempPath::new("repl_util.k");
+        let code = format!(
+            "import \"{}\"; def quad(x) square(square(x));",
+            math.file_name().unwrap().to_str().unwrap()
+        );
+        std::fs::write(&*util, code).unwrap();
+
+        let mut repl = Repl::new();
+        let code = format!(
+            "import \"{}\"; import \"{}\"; quad(2)",
+            math.display(),
+            util.display()
+        );
+        assert_eq!(repl.eval_str(&code).unwrap(), Some(16.0));
+        assert_eq!(repl.items.len(), 2);
+    }
+
+    #[test]
+    fn complete_defined_functions() {
+        let mut repl = Repl::new();
+        repl.process_line("def foo(x) x;".into());
+        repl.process_line("extern fabs(x);".into());
+
+        let helper = ReplHelper::new(Vec::new(), Rc::clone(&repl.names));
+        let history = rustyline::history::History::new();
+        let ctx = rustyline::Context::new(&history);
+        let complete = |line: &str| {
+            let (idx, candidates) = helper.complete(line, line.len(), &ctx).unwrap();
+            let candidates = candidates
+                .iter()
+                .map(|c| c.display().to_string())
+                .collect::<Vec<_>>();
+            (idx, candidates)
+        };
+
+        assert_eq!(complete("fo"), (0, vec!["foo".to_string()]));
+        assert_eq!(
+            complete("1 + f"),
+            (4, vec!["fabs".to_string(), "foo".to_string()])
+        );
+        assert_eq!(complete("bar(fa"), (4, vec!["fabs".to_string()]));
+        assert_eq!(complete(".doc fo"), (5, vec!["foo".to_string()]));
     }
 }
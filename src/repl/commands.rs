@@ -1,19 +1,47 @@
 //! Commands for the REPL.
 
 use super::Repl;
+use inkwell::context::Context;
 use kaleidoscope::{
+    analysis::resolve_names,
+    codegen::{create_pass_manager, parse_opt_level, Compiler},
     error,
-    parse::FrontendDatabase,
+    parse::{
+        ast::{Item, ItemKind},
+        FrontendDatabase,
+    },
     pretty::Pretty,
-    source::{File, SourceDatabase},
+    source::File,
+    Diagnostic,
 };
-use std::{collections::HashMap, sync::Arc};
+use lasso::ThreadedRodeo;
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// The width that is used to pretty print code, if no other width is given.
+const PRETTY_WIDTH: usize = 50;
 
 pub fn default_commands() -> HashMap<&'static str, fn(&mut Repl, &str)> {
     let mut cmds = HashMap::<&'static str, fn(&mut Repl, &str)>::new();
     cmds.insert("help", help_command);
     cmds.insert("h", help_command);
     cmds.insert("ast", ast_command);
+    cmds.insert("ir", ir_command);
+    cmds.insert("load", load_command);
+    cmds.insert("reset", reset_command);
+    cmds.insert("opt", opt_command);
+    cmds.insert("type", type_command);
+    cmds.insert("history", history_command);
+    cmds.insert("clear-history", clear_history_command);
+    cmds.insert("save", save_command);
+    cmds.insert("time", time_command);
+    cmds.insert("list", list_command);
+    cmds.insert("version", version_command);
+    cmds.insert("doc", doc_command);
     cmds
 }
 
@@ -22,30 +50,494 @@ fn help_command(_repl: &mut Repl, _args: &str) {
         "\
 Available commands:
     {p}help|h       Shows this message
-    {p}ast          Pretty prints the parsed AST.
+    {p}ast [width]  Pretty prints the parsed AST, wrapped at width columns. (default: 50)
+    {p}ir           Prints the generated LLVM IR.
+    {p}load <path>  Loads and runs the given file.
+    {p}reset        Removes all definitions of this session.
+    {p}opt <level>  Sets the optimization level (0-3).
+    {p}type         Shows how the parser classified the input.
+    {p}history [n]  Shows the last n entries of the history. (default: 20)
+    {p}clear-history
+                  Clears the history and removes the history file.
+    {p}save <path>  Saves all definitions of this session into the given file.
+    {p}time <code>  Runs the code and shows how long parsing, codegen and execution took.
+    {p}list         Lists all definitions of this session.
+    {p}version      Shows the version of the compiler and of LLVM.
+    {p}doc <name>   Shows the definition of the given function, extern or global.
 ",
         p = super::PREFIX
     )
 }
 
-fn ast_command(repl: &mut Repl, code: &str) {
+fn ast_command(repl: &mut Repl, args: &str) {
+    let (width, code) = split_width(args);
+    match pretty_items(repl, code, width) {
+        Ok(items) => {
+            for item in items {
+                println!("=>");
+                println!("{}", item);
+            }
+        }
+        Err(err) => error::emit(&repl.db, err).expect("failed to emit diagnostic"),
+    };
+}
+
+/// Splits the optional width in front of the code for `.ast`.
+///
+/// A single number is treated as code, not as the width.
+fn split_width(args: &str) -> (usize, &str) {
+    let args = args.trim_start();
+    let end = args.find(char::is_whitespace).unwrap_or_else(|| args.len());
+    let (width, code) = args.split_at(end);
+    match width.parse::<usize>() {
+        Ok(width) if !code.trim().is_empty() => (width, code),
+        _ => (PRETTY_WIDTH, args),
+    }
+}
+
+/// Parses the code and pretty prints every item, wrapped at `width` columns.
+fn pretty_items(repl: &Repl, code: &str, width: usize) -> Result<Vec<String>, Diagnostic> {
     let file = File::new(Arc::new("pretty".into()), Arc::new(code.into()));
-    let file = repl.db.intern_file(file);
+    let file = repl.db.add_file(file);
+
+    let items = repl.db.parse(file).map_err(Into::<Diagnostic>::into)?;
+    let rodeo = repl.db.rodeo();
+    Ok(items
+        .iter()
+        .map(|item| item.pretty_to_string(&rodeo, width))
+        .collect())
+}
+
+fn ir_command(repl: &mut Repl, code: &str) {
+    match module_ir(repl, code) {
+        Ok(ir) => print!("{}", ir),
+        Err(err) => error::emit(&repl.db, err).expect("failed to emit diagnostic"),
+    }
+}
+
+/// Compiles the code together with all definitions of the session,
+/// without running it, and returns the IR of the module.
+fn module_ir(repl: &Repl, code: &str) -> Result<String, Diagnostic> {
+    let file = File::new(Arc::new("ir".into()), Arc::new(code.into()));
+    let file = repl.db.add_file(file);
+
+    let ast = repl.db.parse(file).map_err(Into::<Diagnostic>::into)?;
+    let items = repl.session_items(&ast);
+    if let Some(err) = resolve_names(&repl.db.rodeo(), file, &items)
+        .into_iter()
+        .next()
+    {
+        return Err(err.into());
+    }
+
+    let ctx = Context::create();
+    let builder = ctx.create_builder();
+    let module = ctx.create_module("repl");
+
+    let fpm = create_pass_manager(&module, repl.opt_level);
+
+    let mut compiler = Compiler::new(file, &ctx, &builder, &fpm, &module, repl.db.rodeo());
+    compiler
+        .compile_to_ir_string(&items)
+        .map_err(Into::<Diagnostic>::into)
+}
+
+fn load_command(repl: &mut Repl, path: &str) {
+    let path = path.trim();
+    match repl.db.load_file(Path::new(path)) {
+        Ok(file) => repl.execute_file(file),
+        Err(err) => {
+            let diagnostic =
+                Diagnostic::error().with_message(format!("failed to read '{}': {}", path, err));
+            error::emit(&repl.db, diagnostic).expect("failed to emit diagnostic");
+        }
+    }
+}
+
+fn reset_command(repl: &mut Repl, _args: &str) {
+    repl.items.clear();
+    repl.update_names();
+    repl.update_operators();
+    repl.db.set_rodeo(Arc::new(Default::default()));
+}
+
+fn opt_command(repl: &mut Repl, level: &str) {
+    match parse_opt_level(level) {
+        Ok(level) => repl.opt_level = level,
+        Err(err) => println!("{}", err),
+    }
+}
+
+fn type_command(repl: &mut Repl, code: &str) {
+    let file = File::new(Arc::new("type".into()), Arc::new(code.into()));
+    let file = repl.db.add_file(file);
 
     match repl.db.parse(file) {
         Ok(items) => {
-            let stdout = std::io::stdout();
-            let mut stdout = stdout.lock();
-            for item in items {
-                println!("=>");
-                let alloc = pretty::Arena::<()>::new();
-                item.pretty(&alloc, &repl.db.rodeo())
-                    .1
-                    .render(50, &mut stdout)
-                    .expect("failed to pretty print item");
-                println!();
+            let rodeo = repl.db.rodeo();
+            for item in items.iter() {
+                println!("{}", item_type(&rodeo, item));
             }
         }
         Err(err) => error::emit(&repl.db, err.into()).expect("failed to emit diagnostic"),
+    }
+}
+
+fn history_command(repl: &mut Repl, count: &str) {
+    let count = match count.trim() {
+        "" => 20,
+        count => match count.parse::<usize>() {
+            Ok(count) => count,
+            Err(_) => {
+                println!("invalid number of entries '{}'", count);
+                return;
+            }
+        },
+    };
+
+    let history = repl.editor.history();
+    let skip = history.len().saturating_sub(count);
+    for (idx, entry) in history.iter().enumerate().skip(skip) {
+        println!("{:>4}  {}", idx + 1, entry);
+    }
+}
+
+fn clear_history_command(repl: &mut Repl, _args: &str) {
+    repl.editor.clear_history();
+    if let Some(path) = &repl.history_path {
+        match std::fs::remove_file(path) {
+            Ok(_) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => println!("failed to remove '{}': {}", path.display(), err),
+        }
+    }
+}
+
+fn save_command(repl: &mut Repl, path: &str) {
+    let path = path.trim();
+    if path.is_empty() {
+        println!("usage: {}save <path>", super::PREFIX);
+        return;
+    }
+
+    let rodeo = repl.db.rodeo();
+    let source = repl
+        .items
+        .iter()
+        .map(|item| item.pretty_to_string(&rodeo, PRETTY_WIDTH) + "\n")
+        .collect::<String>();
+
+    if let Err(err) = std::fs::write(path, source) {
+        let diagnostic =
+            Diagnostic::error().with_message(format!("failed to write '{}': {}", path, err));
+        error::emit(&repl.db, diagnostic).expect("failed to emit diagnostic");
+    }
+}
+
+fn time_command(repl: &mut Repl, code: &str) {
+    match time_code(repl, code) {
+        Ok((result, timings)) => {
+            if let Some(result) = result {
+                println!("=> {}", result);
+            }
+            println!("parse:     {:?}", timings.parse);
+            println!("codegen:   {:?}", timings.codegen);
+            println!("execution: {:?}", timings.execution);
+        }
+        Err(err) => error::emit(&repl.db, err).expect("failed to emit diagnostic"),
+    }
+}
+
+fn version_command(_repl: &mut Repl, _args: &str) {
+    println!("{}", crate::version());
+}
+
+fn list_command(repl: &mut Repl, _args: &str) {
+    let definitions = definitions(repl);
+    if definitions.is_empty() {
+        println!("nothing is defined yet");
+    }
+    for definition in definitions {
+        println!("{}", definition);
+    }
+}
+
+fn doc_command(repl: &mut Repl, name: &str) {
+    let name = name.trim();
+    if name.is_empty() {
+        println!("usage: {}doc <name>", super::PREFIX);
+        return;
+    }
+
+    match definition_source(repl, name) {
+        Some(source) => println!("{}", source),
+        None => println!("'{}' is not defined", name),
+    }
+}
+
+/// Pretty prints the function, extern or global with the given name.
+fn definition_source(repl: &Repl, name: &str) -> Option<String> {
+    let rodeo = repl.db.rodeo();
+    let spur = rodeo.get(name)?;
+    repl.items
+        .iter()
+        .find(|item| match &item.kind {
+            ItemKind::Function { name, .. }
+            | ItemKind::Extern { name, .. }
+            | ItemKind::Global { name, .. } => name.spur == spur,
+            ItemKind::Operator { .. } | ItemKind::Import { .. } => false,
+        })
+        .map(|item| item.pretty_to_string(&rodeo, PRETTY_WIDTH))
+}
+
+/// Describes every definition of the session, in the order they were defined.
+fn definitions(repl: &Repl) -> Vec<String> {
+    let rodeo = repl.db.rodeo();
+    repl.items
+        .iter()
+        .map(|item| item_type(&rodeo, item))
+        .collect()
+}
+
+/// The time that was spent in each step of running some code.
+#[derive(Debug)]
+struct Timings {
+    parse: Duration,
+    codegen: Duration,
+    execution: Duration,
+}
+
+/// Compiles and runs the given code together with all definitions of the session,
+/// without adding the new definitions to the session.
+fn time_code(repl: &Repl, code: &str) -> Result<(Option<f64>, Timings), Diagnostic> {
+    let file = File::new(Arc::new("time".into()), Arc::new(code.into()));
+    let file = repl.db.add_file(file);
+
+    let start = Instant::now();
+    let ast = repl.db.parse(file).map_err(Into::<Diagnostic>::into)?;
+    let parse = start.elapsed();
+
+    let items = repl.session_items(&ast);
+    if let Some(err) = resolve_names(&repl.db.rodeo(), file, &items)
+        .into_iter()
+        .next()
+    {
+        return Err(err.into());
+    }
+
+    let ctx = Context::create();
+    let builder = ctx.create_builder();
+    let module = ctx.create_module("time");
+    let fpm = create_pass_manager(&module, repl.opt_level);
+
+    let start = Instant::now();
+    let mut compiler = Compiler::new(file, &ctx, &builder, &fpm, &module, repl.db.rodeo());
+    compiler
+        .compile_items(&items)
+        .map_err(Into::<Diagnostic>::into)?;
+    let codegen = start.elapsed();
+
+    let start = Instant::now();
+    let result = super::main_result(compiler.run_main(repl.opt_level))?;
+    let execution = start.elapsed();
+
+    let timings = Timings {
+        parse,
+        codegen,
+        execution,
     };
+    Ok((result, timings))
+}
+
+/// Describes what kind of item the parser produced for the given item.
+fn item_type(rodeo: &ThreadedRodeo, item: &Item) -> String {
+    let arguments = |count: usize| match count {
+        1 => "1 argument".to_string(),
+        count => format!("{} arguments", count),
+    };
+
+    if item.is_top_level_expr() {
+        return "expression".into();
+    }
+
+    match &item.kind {
+        ItemKind::Function { name, args, .. } => format!(
+            "{}: function with {}",
+            rodeo.resolve(&name.spur),
+            arguments(args.len())
+        ),
+        ItemKind::Extern {
+            name,
+            args,
+            variadic,
+            ..
+        } => format!(
+            "{}: {}extern with {}",
+            rodeo.resolve(&name.spur),
+            if *variadic { "variadic " } else { "" },
+            arguments(args.len())
+        ),
+        ItemKind::Global { name, .. } => format!("{}: global", rodeo.resolve(&name.spur)),
+        ItemKind::Import { path } => format!("import of '{}'", path),
+        ItemKind::Operator {
+            op,
+            is_binary,
+            args,
+            ..
+        } => {
+            let kind = if *is_binary { "binary" } else { "unary" };
+            format!("{}{}: operator with {}", kind, op, arguments(args.len()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempPath;
+
+    fn types(code: &str) -> Vec<String> {
+        let repl = Repl::new();
+        let items = repl.db.parse_str(code).unwrap();
+        let rodeo = repl.db.rodeo();
+        items.iter().map(|item| item_type(&rodeo, item)).collect()
+    }
+
+    #[test]
+    fn item_types() {
+        let code = "def foo(a b) a; extern sin(x); extern printd(...); \
+                    def unary ~ (v) v; var g; foo(1, 2)";
+        assert_eq!(
+            types(code),
+            [
+                "foo: function with 2 arguments",
+                "sin: extern with 1 argument",
+                "printd: variadic extern with 0 arguments",
+                "unary~: operator with 1 argument",
+                "g: global",
+                "expression",
+            ]
+        );
+    }
+
+    #[test]
+    fn ast_width() {
+        assert_eq!(split_width("80 1 + 2"), (80, " 1 + 2"));
+        assert_eq!(split_width(" 1 + 2"), (PRETTY_WIDTH, "1 + 2"));
+        assert_eq!(split_width("42"), (PRETTY_WIDTH, "42"));
+        assert_eq!(split_width("foo(1)"), (PRETTY_WIDTH, "foo(1)"));
+
+        let repl = Repl::new();
+        let call = "foo(aaaaaaaaaa + bbbbbbbbbb, cccccccccc * dddddddddd, eeeeeeeeee)";
+        let code = format!("def f() {};", call);
+
+        let narrow = pretty_items(&repl, &code, PRETTY_WIDTH).unwrap();
+        assert!(!narrow[0].contains(call), "{}", narrow[0]);
+
+        let (width, code) = split_width(&format!("80 {}", code));
+        let wide = pretty_items(&repl, code, width).unwrap();
+        assert_eq!(wide, [format!("def f()\n  {};", call)]);
+
+        assert!(pretty_items(&repl, "def f(", 80).is_err());
+    }
+
+    #[test]
+    fn clear_history() {
+        let path = TempPath::new("history");
+        std::fs::write(&*path, "def foo() 1;\n").unwrap();
+
+        let mut repl = Repl::new();
+        repl.history_path = Some(path.to_path_buf());
+        repl.process_line("def foo() 1;".into());
+        repl.process_line(".history".into());
+        assert_eq!(repl.editor.history().len(), 2);
+
+        repl.process_line(".clear-history".into());
+        assert!(repl.editor.history().is_empty());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn save_session() {
+        let path = TempPath::new("save.k");
+
+        let mut repl = Repl::new();
+        repl.process_line("def add(a b) a + b;".into());
+        repl.process_line("add(1, 2)".into());
+        repl.process_line(format!(".save {}", path.display()));
+
+        let source = std::fs::read_to_string(&*path).unwrap();
+        let items = repl.db.parse_str(&source).unwrap();
+        let rodeo = repl.db.rodeo();
+        let types = items
+            .iter()
+            .map(|item| item_type(&rodeo, item))
+            .collect::<Vec<_>>();
+        assert_eq!(types, ["add: function with 2 arguments"]);
+    }
+
+    #[test]
+    fn list_definitions() {
+        let mut repl = Repl::new();
+        assert!(definitions(&repl).is_empty());
+
+        repl.process_line("def add(a b) a + b;".into());
+        repl.process_line("extern sin(x);".into());
+        repl.process_line("add(1, 2)".into());
+        repl.process_line("def add(a) a;".into());
+        assert_eq!(
+            definitions(&repl),
+            [
+                "sin: extern with 1 argument",
+                "add: function with 1 argument"
+            ]
+        );
+    }
+
+    #[test]
+    fn show_definition() {
+        let mut repl = Repl::new();
+        repl.process_line("def add(a b) a + b;".into());
+        repl.process_line("extern sin(x);".into());
+        repl.process_line("var g = 2;".into());
+        repl.process_line("add(1, 2)".into());
+
+        assert_eq!(
+            definition_source(&repl, "add").unwrap(),
+            "def add(a b)\n  a + b;"
+        );
+        assert_eq!(definition_source(&repl, "sin").unwrap(), "extern sin(x);");
+        assert_eq!(definition_source(&repl, "g").unwrap(), "var g = 2;");
+        assert_eq!(definition_source(&repl, "main"), None);
+        assert_eq!(definition_source(&repl, "foo"), None);
+
+        repl.process_line("def add(a) a;".into());
+        assert_eq!(definition_source(&repl, "add").unwrap(), "def add(a)\n  a;");
+    }
+
+    #[test]
+    fn ir_of_session() {
+        let mut repl = Repl::new();
+        repl.eval_str("def foo(x) x;").unwrap();
+
+        let ir = module_ir(&repl, "foo(1)").unwrap();
+        assert!(ir.contains("define double @foo"), "{}", ir);
+        assert!(ir.contains("call double @foo"), "{}", ir);
+        assert!(module_ir(&repl, "bar(1)").is_err());
+    }
+
+    #[test]
+    fn time_code_result() {
+        let mut repl = Repl::new();
+        repl.eval_str("def square(x) x * x;").unwrap();
+
+        let (result, _) = time_code(&repl, "square(3)").unwrap();
+        assert_eq!(result, Some(9.0));
+        assert!(time_code(&repl, "square(").is_err());
+        // Timed code does not define anything.
+        assert!(time_code(&repl, "def foo() 1;").unwrap().0.is_none());
+        assert!(time_code(&repl, "foo()").is_err());
+
+        time_command(&mut repl, "square(3)");
+    }
 }
@@ -1,5 +1,6 @@
 use super::PREFIX;
 use ansi_term::Style;
+use kaleidoscope::parse::token::{Kind, TokenStream};
 use rustyline::{
     completion::{extract_word, Candidate, Completer},
     highlight::{Highlighter, MatchingBracketHighlighter},
@@ -8,19 +9,25 @@ use rustyline::{
     Context,
 };
 use rustyline_derive::Helper;
-use std::borrow::Cow;
+use std::{borrow::Cow, cell::RefCell, collections::BTreeSet, rc::Rc};
+
+/// The characters that separate identifiers while completing code.
+const BREAK_CHARS: &[u8] = b" \t\n(),;+-*/<>=!:|&";
 
 #[derive(Helper)]
 pub(super) struct ReplHelper {
     highlighter: MatchingBracketHighlighter,
     commands: Vec<&'static str>,
+    /// The names of all functions defined in the current session.
+    names: Rc<RefCell<BTreeSet<String>>>,
 }
 
 impl ReplHelper {
-    pub fn new(commands: Vec<&'static str>) -> Self {
+    pub fn new(commands: Vec<&'static str>, names: Rc<RefCell<BTreeSet<String>>>) -> Self {
         Self {
             highlighter: Default::default(),
             commands,
+            names,
         }
     }
 }
@@ -49,18 +56,18 @@ impl Highlighter for ReplHelper {
     }
 }
 
-/// Wrapper around a `&'static str` to be used for completion candidates.
+/// Wrapper around a `String` to be used for completion candidates.
 pub struct CompletionCandidate {
-    display: &'static str,
+    display: String,
 }
 
 impl Candidate for CompletionCandidate {
     fn display(&self) -> &str {
-        self.display
+        &self.display
     }
 
     fn replacement(&self) -> &str {
-        self.display
+        &self.display
     }
 }
 
@@ -73,17 +80,35 @@ impl Completer for ReplHelper {
         pos: usize,
         _ctx: &Context<'_>,
     ) -> rustyline::Result<(usize, Vec<Self::Candidate>)> {
-        let (idx, word) = extract_word(line, pos, None, &[]);
-        if !line.starts_with(PREFIX) {
-            return Ok((0, vec![]));
+        // The arguments of commands, like the name for `.doc`, are completed like code.
+        if !line.starts_with(PREFIX) || line[..pos].contains(' ') {
+            let (idx, word) = extract_word(line, pos, None, BREAK_CHARS);
+            if word.is_empty() {
+                return Ok((idx, vec![]));
+            }
+
+            let names = self
+                .names
+                .borrow()
+                .iter()
+                .filter(|name| name.starts_with(word))
+                .map(|name| CompletionCandidate {
+                    display: name.clone(),
+                })
+                .collect::<Vec<_>>();
+            return Ok((idx, names));
         }
+
+        let (idx, word) = extract_word(line, pos, None, &[]);
         let word = word.trim_matches(PREFIX);
 
         let commands = self
             .commands
             .iter()
             .filter(|cmd| cmd.starts_with(word))
-            .map(|x| CompletionCandidate { display: x })
+            .map(|x| CompletionCandidate {
+                display: x.to_string(),
+            })
             .collect::<Vec<_>>();
 
         Ok((idx + 1, commands))
@@ -106,28 +131,99 @@ impl Hinter for ReplHelper {
 
 impl Validator for ReplHelper {
     fn validate(&self, ctx: &mut ValidationContext<'_>) -> rustyline::Result<ValidationResult> {
-        let input = ctx.input();
-        let mut stack = vec![];
-
-        for c in input.chars() {
-            match c {
-                '(' | '[' | '{' => stack.push(c),
-                ')' | ']' | '}' => match (stack.pop(), c) {
-                    (Some('('), ')') | (Some('['), ']') | (Some('{'), '}') => {}
-                    (_, _) => {
-                        return Ok(ValidationResult::Invalid(Some(
-                            "extra closing delimiter".to_string(),
-                        )));
-                    }
-                },
-                _ => continue,
-            }
-        }
+        Ok(validate_input(ctx.input()))
+    }
+}
+
+/// Checks if the input is complete, or if the user has to continue it on the next line.
+///
+/// The input is incomplete if it contains unclosed delimiters or a block comment that
+/// was never closed, or if it ends with a token that must be followed by an expression.
+/// Commands are always complete, because their arguments are not code.
+fn validate_input(input: &str) -> ValidationResult {
+    if input.trim_start().starts_with(PREFIX) {
+        return ValidationResult::Valid(None);
+    }
 
-        if stack.is_empty() {
-            Ok(ValidationResult::Valid(None))
-        } else {
-            Ok(ValidationResult::Incomplete)
+    let mut stack = vec![];
+
+    for c in input.chars() {
+        match c {
+            '(' | '[' | '{' => stack.push(c),
+            ')' | ']' | '}' => match (stack.pop(), c) {
+                (Some('('), ')') | (Some('['), ']') | (Some('{'), '}') => {}
+                (_, _) => {
+                    return ValidationResult::Invalid(Some("extra closing delimiter".to_string()));
+                }
+            },
+            _ => continue,
         }
     }
+
+    if !stack.is_empty() {
+        return ValidationResult::Incomplete;
+    }
+
+    match TokenStream::new(input).last() {
+        Some(token) if token.is_unterminated_comment() => ValidationResult::Incomplete,
+        Some(token) => match token.kind {
+            Kind::Then | Kind::Else | Kind::In | Kind::Operator => ValidationResult::Incomplete,
+            _ => ValidationResult::Valid(None),
+        },
+        None => ValidationResult::Valid(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_incomplete(input: &str) -> bool {
+        matches!(validate_input(input), ValidationResult::Incomplete)
+    }
+
+    #[test]
+    fn unclosed_delimiters() {
+        assert!(is_incomplete("def f(x"));
+        assert!(is_incomplete("def f(x) {"));
+        assert!(!is_incomplete("def f(x) x"));
+        assert!(matches!(
+            validate_input("f(x))"),
+            ValidationResult::Invalid(_)
+        ));
+    }
+
+    #[test]
+    fn trailing_keywords() {
+        assert!(is_incomplete("if x then"));
+        assert!(is_incomplete("if x then 1 else"));
+        assert!(is_incomplete("var x = 1 in"));
+        assert!(is_incomplete("for i = 0, i < 10 in # loop body follows"));
+        assert!(!is_incomplete("if x then 1 else 2"));
+    }
+
+    #[test]
+    fn trailing_operators() {
+        assert!(is_incomplete("1 +"));
+        assert!(is_incomplete("x <="));
+        assert!(is_incomplete("var x ="));
+        assert!(!is_incomplete("1 + 2"));
+    }
+
+    #[test]
+    fn commands_are_complete() {
+        assert!(!is_incomplete(".save /tmp/out/"));
+        assert!(!is_incomplete(".time 1 +"));
+        assert!(!is_incomplete("  .ast 40 def f("));
+        assert!(matches!(
+            validate_input(".load f(x))"),
+            ValidationResult::Valid(_)
+        ));
+    }
+
+    #[test]
+    fn unterminated_comment() {
+        assert!(is_incomplete("1 #* comment"));
+        assert!(!is_incomplete("1 #* comment *#"));
+    }
 }
@@ -41,7 +41,15 @@ pub struct File {
 }
 
 impl File {
+    /// Creates a new `File`.
+    ///
+    /// A leading UTF-8 byte order mark is stripped from the source, so all
+    /// spans and byte offsets are relative to the source without the BOM.
     pub fn new(name: Arc<SmolStr>, source: Arc<String>) -> Self {
+        let source = match source.strip_prefix('\u{feff}') {
+            Some(stripped) => Arc::new(stripped.to_string()),
+            None => source,
+        };
         Self { name, source }
     }
 }
@@ -72,6 +80,11 @@ pub trait SourceDatabase: salsa::Database {
 
     /// Returns the start index of the line in the file.
     fn line_start(&self, file: FileId, line_index: usize) -> Option<usize>;
+
+    /// Returns the zero based line and column of the given byte index in the file.
+    ///
+    /// The column counts UTF-8 characters, not bytes.
+    fn line_column(&self, file: FileId, byte_index: usize) -> Option<(usize, usize)>;
 }
 
 /// The implementation for the `source` query.
@@ -112,6 +125,13 @@ fn line_range(db: &dyn SourceDatabase, file: FileId, line_index: usize) -> Optio
     Some(line..next_line)
 }
 
+fn line_column(db: &dyn SourceDatabase, file: FileId, byte_index: usize) -> Option<(usize, usize)> {
+    let line = db.line_index(file, byte_index)?;
+    let start = db.line_start(file, line)?;
+    let column = db.source(file).get(start..byte_index)?.chars().count();
+    Some((line, column))
+}
+
 /// A atomic counted reference to a `String`, which implements `AsRef<str>`
 #[derive(Debug)]
 pub struct StringRef {
@@ -164,4 +184,71 @@ impl<'a> codespan_reporting::files::Files<'a> for FileCache<'a> {
     fn line_range(&'a self, id: Self::FileId, line_index: usize) -> Option<Range<usize>> {
         self.db.line_range(id, line_index)
     }
+
+    fn column_number(
+        &'a self,
+        id: Self::FileId,
+        _line_index: usize,
+        byte_index: usize,
+    ) -> Option<usize> {
+        let (_, column) = self.db.line_column(id, byte_index)?;
+        Some(column + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CompilerDatabase;
+
+    fn file(db: &CompilerDatabase, source: &str) -> FileId {
+        db.intern_file(File::new(Arc::new("test".into()), Arc::new(source.into())))
+    }
+
+    #[test]
+    fn line_column_ascii() {
+        let db = CompilerDatabase::default();
+        let file = file(&db, "def foo(x)\n  x + 1;");
+
+        assert_eq!(db.line_column(file, 0), Some((0, 0)));
+        assert_eq!(db.line_column(file, 4), Some((0, 4)));
+        assert_eq!(db.line_column(file, 13), Some((1, 2)));
+    }
+
+    #[test]
+    fn line_column_non_ascii() {
+        let db = CompilerDatabase::default();
+        let file = file(&db, "# äöü\n# ü x");
+
+        // 'x' is the 5th character on the second line, but the 6th byte.
+        let x = "# äöü\n# ü ".len();
+        assert_eq!(db.line_column(file, x), Some((1, 5)));
+        // A byte index in the middle of a character has no column.
+        assert_eq!(db.line_column(file, 3), None);
+    }
+
+    #[test]
+    fn line_column_crlf() {
+        let db = CompilerDatabase::default();
+        let source = "def foo(x)\r\n  x + 1;\r\nfoo(2)";
+        let file = file(&db, source);
+
+        assert_eq!(
+            db.line_column(file, source.find('+').unwrap()),
+            Some((1, 4))
+        );
+        assert_eq!(
+            db.line_column(file, source.find("foo(2)").unwrap()),
+            Some((2, 0))
+        );
+    }
+
+    #[test]
+    fn strip_byte_order_mark() {
+        let db = CompilerDatabase::default();
+        let file = file(&db, "\u{feff}def foo(x) x;");
+
+        assert_eq!(db.source(file).as_str(), "def foo(x) x;");
+        assert_eq!(db.line_column(file, 4), Some((0, 4)));
+    }
 }
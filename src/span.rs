@@ -9,6 +9,7 @@ use std::ops::{Deref, DerefMut, Index, Range};
 ///
 /// [`codespan::Span`]: https://docs.rs/codespan/0.9.5/codespan/struct.Span.html
 #[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Span {
     start: usize,
     end: usize,
@@ -20,6 +21,11 @@ impl Span {
         Self { start, end }
     }
 
+    /// Creates a new `Span` that starts at `start` and is `len` bytes long.
+    pub fn with_len(start: usize, len: usize) -> Self {
+        Self::new(start, start + len)
+    }
+
     /// The `start` of self.
     pub fn start(&self) -> usize {
         self.start
@@ -30,6 +36,30 @@ impl Span {
         self.end
     }
 
+    /// The length of `self` in bytes.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Checks if `self` has a length of zero.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Checks if the given byte offset is inside `self`.
+    ///
+    /// The `end` of the span is exclusive, the same way as in `index_in`.
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset < self.end
+    }
+
+    /// Removes the first `n` bytes from `self`.
+    ///
+    /// The returned span is empty if `self` is shorter than `n` bytes.
+    pub fn trim_start(self, n: usize) -> Self {
+        Self::new((self.start + n).min(self.end), self.end)
+    }
+
     /// Merge two spans together.
     pub fn merge(self, other: Self) -> Self {
         let start = self.start.min(other.start);
@@ -37,6 +67,15 @@ impl Span {
         Self::new(start, end)
     }
 
+    /// Merges all given spans together.
+    ///
+    /// Returns `None` if the iterator is empty.
+    pub fn merge_all(spans: impl IntoIterator<Item = Span>) -> Option<Self> {
+        let mut spans = spans.into_iter();
+        let first = spans.next()?;
+        Some(spans.fold(first, Span::merge))
+    }
+
     /// A helper function to tell whether two spans do not overlap.
     pub fn disjoint(&self, other: &Span) -> bool {
         let (first, last) = if self.end < other.end {
@@ -48,6 +87,8 @@ impl Span {
     }
 
     /// Returns a reference to the data that is located at the span.
+    ///
+    /// Panics if the span is out of range, use `get_in` for a checked version.
     pub fn index_in<'input, I, N>(&self, val: &'input I) -> &'input I::Output
     where
         I: Index<Range<N>>,
@@ -58,6 +99,14 @@ impl Span {
         val.index(start..end)
     }
 
+    /// Returns the text that is located at the span, or `None` if the span is
+    /// out of range or doesn't start and end on a character boundary.
+    ///
+    /// This can happen if a span is used for a different file than it belongs to.
+    pub fn get_in<'a>(&self, val: &'a str) -> Option<&'a str> {
+        val.get(self.start..self.end)
+    }
+
     pub fn locate<T>(self, file: FileId, data: T) -> Locatable<T> {
         Locatable {
             data,
@@ -116,6 +165,15 @@ impl<T> Locatable<T> {
     pub fn destruct(self) -> (T, Span, FileId) {
         (self.data, self.span, self.file)
     }
+
+    /// Transforms the data of this `Locatable`, while keeping the span and file.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Locatable<U> {
+        Locatable {
+            data: f(self.data),
+            span: self.span,
+            file: self.file,
+        }
+    }
 }
 
 impl<T> Deref for Locatable<T> {
@@ -142,4 +200,72 @@ mod tests {
         let second = Span::new(1, 3);
         assert!(!first.disjoint(&second));
     }
+
+    #[test]
+    fn test_len() {
+        assert_eq!(Span::new(2, 7).len(), 5);
+        assert!(!Span::new(2, 7).is_empty());
+        assert!(Span::new(4, 4).is_empty());
+    }
+
+    #[test]
+    fn test_merge_all() {
+        assert_eq!(Span::merge_all(Vec::new()), None);
+        assert_eq!(
+            Span::merge_all(vec![Span::new(3, 5)]),
+            Some(Span::new(3, 5))
+        );
+
+        let spans = vec![Span::new(4, 6), Span::new(1, 2), Span::new(8, 9)];
+        assert_eq!(Span::merge_all(spans), Some(Span::new(1, 9)));
+    }
+
+    #[test]
+    fn test_get_in() {
+        let source = "def föo(x) x;";
+        assert_eq!(Span::new(0, 3).get_in(source), Some("def"));
+        assert_eq!(Span::new(4, 8).get_in(source), Some("föo"));
+        assert_eq!(Span::new(14, 14).get_in(source), Some(""));
+
+        assert_eq!(Span::new(12, 20).get_in(source), None);
+        assert_eq!(Span::new(20, 22).get_in(source), None);
+        // `ö` is two bytes long.
+        assert_eq!(Span::new(4, 6).get_in(source), None);
+    }
+
+    #[test]
+    fn test_with_len() {
+        assert_eq!(Span::with_len(3, 4), Span::new(3, 7));
+        assert!(Span::with_len(5, 0).is_empty());
+    }
+
+    #[test]
+    fn test_trim_start() {
+        assert_eq!(Span::new(2, 7).trim_start(2), Span::new(4, 7));
+        assert_eq!(Span::new(2, 7).trim_start(0), Span::new(2, 7));
+        assert_eq!(Span::new(2, 7).trim_start(10), Span::new(7, 7));
+    }
+
+    #[test]
+    fn test_map() {
+        use crate::error::SyntaxError;
+
+        let file = FileId::default();
+        let err = Span::new(3, 7).locate(file, SyntaxError::ZeroStep);
+        let err = err.map(|err| format!("{:?}", err));
+
+        assert_eq!(err.data(), "ZeroStep");
+        assert_eq!(err.span(), Span::new(3, 7));
+        assert_eq!(err.file(), file);
+    }
+
+    #[test]
+    fn test_contains() {
+        let span = Span::new(2, 5);
+        assert!(!span.contains(1));
+        assert!(span.contains(2));
+        assert!(span.contains(4));
+        assert!(!span.contains(5));
+        assert!(!Span::new(3, 3).contains(3));
+    }
 }
@@ -0,0 +1,232 @@
+mod common;
+
+use common::TempPath;
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+/// Writes `code` into a temporary `.k` file and returns its path.
+fn source_file(name: &str, code: &str) -> TempPath {
+    let path = TempPath::new(&format!("{}.k", name));
+    std::fs::write(&*path, code).expect("failed to write source file");
+    path
+}
+
+/// Runs the compiler on the given code and returns the process output
+/// together with the path of the object file.
+fn run_compiler(name: &str, code: &str, flags: &[&str]) -> (Output, TempPath) {
+    let file = source_file(name, code);
+    let output = TempPath::new(&format!("{}.o", name));
+
+    let result = Command::new(env!("CARGO_BIN_EXE_kaleidoscope"))
+        .args(flags)
+        .arg("-o")
+        .arg(&*output)
+        .arg(&*file)
+        .output()
+        .expect("failed to run compiler");
+    (result, output)
+}
+
+/// Runs the compiler on the given code and returns the captured stdout.
+fn compile(name: &str, code: &str, flags: &[&str]) -> String {
+    let (result, _) = run_compiler(name, code, flags);
+    let stdout = String::from_utf8(result.stdout).expect("stdout is not valid utf8");
+    assert!(result.status.success(), "compiler failed:\n{}", stdout);
+    stdout
+}
+
+#[test]
+fn emit_ast() {
+    let stdout = compile("emit_ast", "def add(a b) a + b;", &["--emit-ast"]);
+    assert_eq!(stdout, "def add(a b)\n  a + b;\n");
+}
+
+#[test]
+fn read_from_stdin() {
+    let output = TempPath::new("stdin.o");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kaleidoscope"))
+        .args(&["--emit-ast", "-o"])
+        .arg(&*output)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run compiler");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"def add(a b) a + b;")
+        .expect("failed to write to stdin");
+
+    let result = child.wait_with_output().expect("failed to run compiler");
+    let stdout = String::from_utf8(result.stdout).expect("stdout is not valid utf8");
+    assert!(result.status.success(), "compiler failed:\n{}", stdout);
+    assert_eq!(stdout, "def add(a b)\n  a + b;\n");
+}
+
+#[test]
+fn emit_lex() {
+    let code = "def foo(x) if x < 1.5 then 2 else x;";
+    let stdout = compile("emit_lex", code, &["--emit-lex"]);
+    assert_eq!(
+        stdout,
+        "\
+Def @ 1:1-1:4 'def'
+Identifier @ 1:5-1:8 'foo'
+LeftParen @ 1:8-1:9 '('
+Identifier @ 1:9-1:10 'x'
+RightParen @ 1:10-1:11 ')'
+If @ 1:12-1:14 'if'
+Identifier @ 1:15-1:16 'x'
+Operator @ 1:17-1:18 '<'
+Number @ 1:19-1:22 '1.5'
+Then @ 1:23-1:27 'then'
+Number @ 1:28-1:29 '2'
+Else @ 1:30-1:34 'else'
+Identifier @ 1:35-1:36 'x'
+Semicolon @ 1:36-1:37 ';'
+"
+    );
+}
+
+#[test]
+fn emit_lex_multiple_lines() {
+    let code = "def f(x)\n  x; # done";
+    let stdout = compile("emit_lex_multiple_lines", code, &["--emit-lex"]);
+    assert_eq!(
+        stdout,
+        "\
+Def @ 1:1-1:4 'def'
+Identifier @ 1:5-1:6 'f'
+LeftParen @ 1:6-1:7 '('
+Identifier @ 1:7-1:8 'x'
+RightParen @ 1:8-1:9 ')'
+Identifier @ 2:3-2:4 'x'
+Semicolon @ 2:4-2:5 ';'
+Comment @ 2:6-2:12 '# done'
+"
+    );
+}
+
+#[test]
+fn emit_ir() {
+    let stdout = compile("emit_ir", "def main() 42;", &["--emit-ir"]);
+    assert!(stdout.contains("define double @main"), "{}", stdout);
+}
+
+#[test]
+fn emit_bitcode() {
+    let (result, output) = run_compiler("emit_bitcode", "def main() 42;", &["--emit-bitcode"]);
+    assert!(result.status.success());
+
+    let bitcode = std::fs::read(&*output).expect("bitcode file does not exist");
+    assert!(
+        bitcode.starts_with(b"BC\xC0\xDE"),
+        "{:x?}",
+        bitcode.get(..4)
+    );
+}
+
+#[test]
+fn run_main() {
+    let code = "def square(x) x * x; def main() square(6) + 6;";
+    let stdout = compile("run_main", code, &["--run"]);
+    assert_eq!(stdout, "42\n");
+}
+
+#[test]
+fn import_function() {
+    let lib = source_file("import_lib", "def square(x) x * x;");
+    let code = format!(
+        "import \"{}\"; def main() square(6) + 6;",
+        lib.file_name().unwrap().to_str().unwrap()
+    );
+    let stdout = compile("import_main", &code, &["--run"]);
+    assert_eq!(stdout, "42\n");
+}
+
+#[test]
+fn version() {
+    let result = Command::new(env!("CARGO_BIN_EXE_kaleidoscope"))
+        .arg("--version")
+        .output()
+        .expect("failed to run compiler");
+    let stdout = String::from_utf8(result.stdout).unwrap();
+
+    assert!(result.status.success());
+    assert!(stdout.starts_with("Kaleidoscope "), "{}", stdout);
+    assert!(stdout.contains("(LLVM "), "{}", stdout);
+}
+
+#[test]
+fn json_diagnostics() {
+    let flags = ["--emit-ast", "--error-format", "json"];
+    let (result, _) = run_compiler("json_diagnostics", "def f() x;", &flags);
+    assert!(!result.status.success());
+
+    // Only the AST is written to stdout, without the abort message.
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    assert_eq!(stdout, "def f()\n  x;\n");
+
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    let lines = stderr.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 1, "{}", stderr);
+    let json: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(json["message"], "unknown variable");
+}
+
+#[test]
+fn color_mode() {
+    let code = "def f() x;";
+    let output = |color| {
+        let (result, _) = run_compiler("color", code, &["--color", color]);
+        assert!(!result.status.success());
+        String::from_utf8(result.stdout).unwrap()
+    };
+
+    let plain = output("never");
+    assert!(plain.contains("unknown variable"), "{}", plain);
+    assert!(!plain.contains('\x1b'), "{}", plain);
+    assert!(output("always").contains('\x1b'));
+}
+
+#[test]
+fn run_without_main() {
+    let (result, _) = run_compiler("run_without_main", "def foo() 1;", &["--run"]);
+    let stdout = String::from_utf8(result.stdout).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stdout.contains("no `main` function to run"), "{}", stdout);
+}
+
+/// Asserts that the compiler succeeded and wrote a non-empty object file.
+fn assert_object_file(name: &str, flags: &[&str]) {
+    let (result, output) = run_compiler(name, "def main() 42;", flags);
+    assert!(result.status.success());
+
+    let metadata = std::fs::metadata(&*output).expect("object file does not exist");
+    assert!(metadata.len() > 0);
+}
+
+#[test]
+fn object_file_for_host() {
+    assert_object_file("host", &[]);
+}
+
+#[test]
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn object_file_for_target() {
+    let triple = format!("{}-unknown-linux-gnu", std::env::consts::ARCH);
+    assert_object_file("target", &["--target", &triple]);
+}
+
+#[test]
+fn unknown_target() {
+    let (result, _) = run_compiler("unknown_target", "def main() 42;", &["--target", "foo"]);
+    let stdout = String::from_utf8(result.stdout).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stdout.contains("unknown target 'foo'"), "{}", stdout);
+}
@@ -0,0 +1,38 @@
+//! Test helpers that are shared by the unit tests of the library and the
+//! binary, and by the integration tests.
+
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+/// A path in the temporary directory that is removed when it's dropped.
+///
+/// The path contains the process id, so concurrent test runs don't share files.
+#[derive(Debug)]
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    pub fn new(name: &str) -> Self {
+        let name = format!("kaleidoscope_{}_{}", std::process::id(), name);
+        Self(std::env::temp_dir().join(name))
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = if self.0.is_dir() {
+            std::fs::remove_dir_all(&self.0)
+        } else {
+            std::fs::remove_file(&self.0)
+        };
+    }
+}